@@ -0,0 +1,277 @@
+// the layout of a .crumb, a gzipped tar
+//
+//  .CRUMB/VERSION          the format version, currently 1
+//  .CRUMB/crumb.toml
+//  .CRUMB/manifest.toml    the files of the payload, see manifest.rs
+//  .CRUMB/...              the scripts and everything else of the package directory
+//  payload/...             what gets installed, payload/usr/bin/hello becomes /usr/bin/hello
+//
+// .CRUMB/ comes first, so the metadata is read without going through the payload.
+// crumbs baked before the layout existed are format 0, they have crumb.toml and the scripts
+// at the root next to the payload.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+
+use flate2::read::GzDecoder;
+use tar::Archive;
+
+use crate::crumb::CrumbInfo;
+use crate::installed::installed_dir;
+use crate::manifest::{Manifest, MANIFEST_NAME};
+use crate::error::{BreadError, IoContext, Result};
+
+pub const FORMAT_VERSION: u32 = 1;
+pub const METADATA_DIR: &str = ".CRUMB";
+pub const PAYLOAD_DIR: &str = "payload";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EntryKind {
+    // relative to .CRUMB/
+    Metadata(PathBuf),
+
+    // relative to the root it's installed into
+    Payload(PathBuf),
+
+    // the .CRUMB/ and payload/ directories themselves, or something that doesn't belong into a crumb
+    Skip,
+
+    // a path with .. or / in front, it could point anywhere
+    Unsafe(PathBuf),
+}
+
+pub struct CrumbArchive {
+    pub path: PathBuf,
+    pub format: u32,
+
+    pub info: CrumbInfo,
+
+    // None for crumbs baked before manifests existed
+    pub manifest: Option<Manifest>,
+
+    // every file of the metadata, relative to .CRUMB/
+    pub metadata: Vec<PathBuf>,
+}
+
+// the path without ./ in front, None if it could point outside of where it's unpacked
+pub fn normalize(path: &Path) -> Option<PathBuf> {
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::Normal(name) => normalized.push(name),
+            _ => return None,
+        }
+    }
+
+    Some(normalized)
+}
+
+// returns the file name if the path is directly at the root of the archive
+pub fn top_level_name(path: &Path) -> Option<String> {
+    let path = normalize(path)?;
+    let mut components = path.components();
+
+    let name = components.next()?.as_os_str().to_str()?.to_string();
+
+    if components.next().is_some() {
+        return None;
+    }
+
+    Some(name)
+}
+
+impl CrumbArchive {
+    // Reads the metadata of a .crumb, the payload isn't touched
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<CrumbArchive> {
+        let path = path.as_ref();
+
+        let mut format = 0;
+        let mut info = None;
+        let mut manifest = None;
+        let mut metadata = vec![];
+
+        let mut archive = CrumbArchive::tar(path)?;
+        for entry in archive.entries().at(path)? {
+            let mut entry = entry.at(path)?;
+            let entry_path = normalize(&entry.path().at(path)?).unwrap_or_default();
+
+            let name = match entry_path.strip_prefix(METADATA_DIR) {
+                Ok(name) => name.to_path_buf(),
+
+                Err(_) if format > 0 => break,
+
+                // format 0 has the metadata at the root, mixed with the payload
+                Err(_) => match top_level_name(&entry_path).as_deref() {
+                    Some("crumb.toml") | Some(MANIFEST_NAME) => entry_path,
+                    _ => continue,
+                },
+            };
+
+            if name.as_os_str().is_empty() || !entry.header().entry_type().is_file() {
+                continue;
+            }
+
+            let file_name = name.to_string_lossy().to_string();
+            let mut data = String::default();
+            if ["VERSION", "crumb.toml", MANIFEST_NAME].contains(&file_name.as_str()) {
+                entry.read_to_string(&mut data).at(path)?;
+            }
+
+            match file_name.as_str() {
+                "VERSION" => {
+                    format = data.trim().parse()
+                        .map_err(|_| BreadError::parse(path.to_string_lossy(), format!("{} is not a crumb format version", data.trim())))?;
+
+                    if format > FORMAT_VERSION {
+                        return Err(BreadError::parse(path.to_string_lossy(), format!("crumb format {} is newer than this bread understands ({})", format, FORMAT_VERSION)));
+                    }
+                }
+
+                "crumb.toml" => info = Some(CrumbInfo::parse(format!("crumb.toml of {}", path.display()), &data)?),
+                MANIFEST_NAME => manifest = Some(Manifest::from_string(&data)?),
+                _ => {}
+            }
+
+            metadata.push(name);
+        }
+
+        let info = info.ok_or_else(|| BreadError::parse(path.to_string_lossy(), "crumb.toml is missing"))?;
+
+        // format 0 only kept the scripts next to crumb.toml
+        if format == 0 {
+            for script in [&info.scripts.install, &info.scripts.uninstall, &info.scripts.build].iter().filter_map(|script| script.as_ref()) {
+                metadata.push(PathBuf::from(script.trim_start_matches("./")));
+            }
+        }
+
+        Ok(CrumbArchive {
+            path: path.to_path_buf(),
+            format,
+            info,
+            manifest,
+            metadata,
+        })
+    }
+
+    // the whole archive, to go through its entries and classify them
+    pub fn tar<P: AsRef<Path>>(path: P) -> Result<Archive<GzDecoder<File>>> {
+        let path = path.as_ref();
+
+        // modes are kept as they are, the extractor decides about setuid bits
+        let mut archive = Archive::new(GzDecoder::new(File::open(path).at(path)?));
+        archive.set_preserve_permissions(true);
+
+        Ok(archive)
+    }
+
+    // Where the metadata goes once the package is installed into root. the package name
+    // has to be a plain file name, `..` or `/etc` would put it anywhere
+    pub fn installed_path<P: AsRef<Path>>(&self, root: P) -> Result<PathBuf> {
+        let name = &self.info.package.name;
+        let mut components = Path::new(name).components();
+
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(_)), None) => Ok(installed_dir(root).join(name)),
+            _ => Err(BreadError::Unsafe {
+                crumb: self.path.display().to_string(),
+                entry: "crumb.toml".to_string(),
+                reason: format!("the package name \"{}\" isn't a plain file name", name),
+            }),
+        }
+    }
+
+    // where an entry of the archive belongs to
+    pub fn classify<P: AsRef<Path>>(&self, entry_path: P) -> EntryKind {
+        let entry_path = match normalize(entry_path.as_ref()) {
+            Some(entry_path) if !entry_path.as_os_str().is_empty() => entry_path,
+            Some(_) => return EntryKind::Skip,
+            None => return EntryKind::Unsafe(entry_path.as_ref().to_path_buf()),
+        };
+
+        if self.format == 0 {
+            let top_level = top_level_name(&entry_path).map(PathBuf::from);
+
+            return match top_level {
+                Some(name) if self.metadata.contains(&name) => EntryKind::Metadata(name),
+                _ => EntryKind::Payload(entry_path),
+            };
+        }
+
+        if let Ok(name) = entry_path.strip_prefix(METADATA_DIR) {
+            if !name.as_os_str().is_empty() {
+                return EntryKind::Metadata(name.to_path_buf());
+            }
+        }
+
+        if let Ok(name) = entry_path.strip_prefix(PAYLOAD_DIR) {
+            if !name.as_os_str().is_empty() {
+                return EntryKind::Payload(name.to_path_buf());
+            }
+        }
+
+        EntryKind::Skip
+    }
+
+    // Reads a file of the metadata, like a script
+    pub fn read_metadata<P: AsRef<Path>>(&self, name: P) -> Result<Vec<u8>> {
+        let name = name.as_ref();
+        let mut archive = CrumbArchive::tar(&self.path)?;
+
+        for entry in archive.entries().at(&self.path)? {
+            let mut entry = entry.at(&self.path)?;
+
+            if self.classify(entry.path().at(&self.path)?) == EntryKind::Metadata(name.to_path_buf()) {
+                let mut data = vec![];
+                entry.read_to_end(&mut data).at(&self.path)?;
+
+                return Ok(data);
+            }
+        }
+
+        Err(BreadError::parse(self.path.to_string_lossy(), format!("{} is missing", name.display())))
+    }
+
+    // Sum of the sizes of everything in the payload
+    pub fn payload_size(&self) -> Result<u64> {
+        let mut archive = CrumbArchive::tar(&self.path)?;
+
+        let mut size = 0;
+        for entry in archive.entries().at(&self.path)? {
+            let entry = entry.at(&self.path)?;
+
+            if let EntryKind::Payload(_) = self.classify(entry.path().at(&self.path)?) {
+                size += entry.header().size().at(&self.path)?;
+            }
+        }
+
+        Ok(size)
+    }
+}
+
+#[test]
+fn classify_entries() {
+    let info = CrumbInfo::from_string("[package]\nname = \"hello\"\nversion = \"1.0\"\n[scripts]\ninstall = \"./install.sh\"\n").unwrap();
+    let mut archive = CrumbArchive {
+        path: PathBuf::from("hello@1.0.crumb"),
+        format: FORMAT_VERSION,
+        info,
+        manifest: None,
+        metadata: vec![PathBuf::from("crumb.toml"), PathBuf::from("install.sh")],
+    };
+
+    assert_eq!(EntryKind::Metadata(PathBuf::from("install.sh")), archive.classify(".CRUMB/install.sh"));
+    assert_eq!(EntryKind::Payload(PathBuf::from("usr/bin/hello")), archive.classify("./payload/usr/bin/hello"));
+    assert_eq!(EntryKind::Payload(PathBuf::from("install.sh")), archive.classify("payload/install.sh"));
+    assert_eq!(EntryKind::Skip, archive.classify("payload"));
+    assert_eq!(EntryKind::Unsafe(PathBuf::from("payload/../etc/passwd")), archive.classify("payload/../etc/passwd"));
+    assert_eq!(EntryKind::Unsafe(PathBuf::from("/etc/passwd")), archive.classify("/etc/passwd"));
+    assert_eq!(EntryKind::Skip, archive.classify("usr/bin/hello"));
+
+    archive.format = 0;
+    assert_eq!(EntryKind::Metadata(PathBuf::from("install.sh")), archive.classify("./install.sh"));
+    assert_eq!(EntryKind::Payload(PathBuf::from("usr/bin/hello")), archive.classify("./usr/bin/hello"));
+    assert_eq!(EntryKind::Skip, archive.classify("."));
+}
@@ -0,0 +1,110 @@
+// /etc/bread/config.toml
+//
+//  [settings]
+//  log-level = "info"
+//  region    = "de"    # mirrors of this region are preferred
+//
+//  [network]
+//  parallel-downloads = 4
+//  retries            = 3
+//  proxy              = "http://proxy.example.org:3128"   # https_proxy/http_proxy otherwise
+//  no-proxy           = ["mirror.example.org"]           # merged with no_proxy
+//  ca-bundles         = ["/etc/bread/certs/internal-ca.pem"]
+//  connect-timeout    = 30                               # seconds
+//  read-timeout       = 60                               # seconds without receiving anything
+//  user-agent         = "bread/1.0.0"
+//  bandwidth-limit    = 512                              # KiB/s over all downloads
+//
+//  [frozen-crumbs]
+//  bread = true
+
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::collections::btree_map::BTreeMap;
+
+use serde::Deserialize;
+
+use crate::error::{BreadError, IoContext, Result};
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Settings {
+    pub log_level: Option<String>,
+    pub region: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Network {
+    pub parallel_downloads: Option<usize>,
+    pub retries: Option<u32>,
+
+    pub proxy: Option<String>,
+    pub no_proxy: Option<Vec<String>>,
+    pub ca_bundles: Option<Vec<PathBuf>>,
+
+    pub connect_timeout: Option<u64>,
+    pub read_timeout: Option<u64>,
+    pub user_agent: Option<String>,
+    pub bandwidth_limit: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Config {
+    #[serde(default)]
+    pub settings: Settings,
+
+    #[serde(default)]
+    pub network: Network,
+
+    #[serde(default)]
+    pub frozen_crumbs: BTreeMap<String, bool>,
+}
+
+impl Config {
+    pub fn from_string<S: AsRef<str>>(data: S) -> Result<Config> {
+        toml::from_str(data.as_ref()).map_err(|err| BreadError::parse("config.toml", err))
+    }
+
+    // Reads config.toml inside of the config path, a missing file is the default config
+    pub fn load<P: AsRef<Path>>(config_path: P) -> Result<Config> {
+        let path = config_path.as_ref().join("config.toml");
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+
+        let mut data = String::default();
+        File::open(&path).at(&path)?.read_to_string(&mut data).at(&path)?;
+
+        toml::from_str(&data).map_err(|err| BreadError::parse(path.to_string_lossy(), err))
+    }
+}
+
+#[test]
+fn parse_config() {
+    let config = Config::from_string(r#"
+        [settings]
+        log-level = "info"
+        region    = "de"
+
+        [network]
+        parallel-downloads = 8
+        proxy              = "http://proxy.example.org:3128"
+        no-proxy           = ["mirror.example.org"]
+        read-timeout       = 10
+
+        [frozen-crumbs]
+        bread = true
+    "#).unwrap();
+
+    assert_eq!(Some("de".to_string()), config.settings.region);
+    assert_eq!(Some(8), config.network.parallel_downloads);
+    assert_eq!(None, config.network.retries);
+    assert_eq!(Some("http://proxy.example.org:3128".to_string()), config.network.proxy);
+    assert_eq!(Some(vec!["mirror.example.org".to_string()]), config.network.no_proxy);
+    assert_eq!(Some(10), config.network.read_timeout);
+    assert_eq!(Some(&true), config.frozen_crumbs.get("bread"));
+    assert!(Config::from_string("").unwrap().frozen_crumbs.is_empty());
+}
@@ -1,5 +1,4 @@
 #[allow(dead_code)]
 pub const PATH_CONFIGS: &str = "/etc/bread";
 pub const PATH_CACHE: &str   = "/var/cache/bread";
-#[allow(dead_code)]
 pub const PATH_INSTALL: &str = "/";
@@ -1,116 +1,190 @@
-use std::fs::File;
-use std::io::Read;
-use std::path::Path;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::thread;
+use std::time::UNIX_EPOCH;
+use std::path::{Path, PathBuf};
 use std::collections::btree_map::BTreeMap;
 
 use toml::from_str;
 use serde::Deserialize;
 
-use flate2::write::GzEncoder;
-use tar::Builder as TarBuilder;
+use flate2::GzBuilder;
+use flate2::read::GzDecoder;
+use tar::{Archive, Builder as TarBuilder, EntryType, Header};
+use sha2::{Sha512, Digest};
 use flate2::Compression;
+use indicatif::ProgressBar;
 
-use crate::style::pkg_name;
-use std::process::Command;
+use crate::style::{pkg_name, uninstall_pg_style};
+use crate::constants::{PATH_CACHE, PATH_CONFIGS, PATH_INSTALL};
+use crate::mirror::{MirrorConfig, MirrorPool};
+use crate::config::Config;
+use crate::net::Downloader;
+use crate::signature::system_keyring;
+use crate::database::{Database, DatabaseEntry};
+use crate::resolver::{resolve, VersionReq};
+use crate::version::Version;
+use crate::error::{BreadError, IoContext, Result};
+use crate::lint::{lint_package, Severity};
+use crate::manifest::{FileType, Manifest, ManifestEntry, MANIFEST_NAME};
+use crate::archive::{normalize, CrumbArchive, EntryKind, FORMAT_VERSION, METADATA_DIR, PAYLOAD_DIR};
+use crate::extract::Extractor;
+use crate::installed::{dependents, InstalledPackage};
+use std::process::{Command, Stdio};
 
 #[derive(Deserialize)]
 pub struct CrumblePackageInfo {
-    name: String,
-    description: Option<String>,
-    version: f32,
-    license: Option<String>,
-    homepage: Option<Vec<String>>,
-    authors: Option<Vec<String>>,
+    pub name: String,
+    pub description: Option<String>,
+    pub version: Version,
+    pub license: Option<String>,
+    pub homepage: Option<Vec<String>>,
+    pub authors: Option<Vec<String>>,
 }
 
 #[derive(Deserialize)]
 pub struct ScriptInfo {
     // basically executes anything like `make`
-    install: Option<String>,
-    uninstall: Option<String>,
-    build: Option<String>,
+    pub install: Option<String>,
+    pub uninstall: Option<String>,
+    pub build: Option<String>,
 }
 
+// the keys of crumb.toml, [dependencies] and [ignore] take any key
+const KNOWN_KEYS: &[(&str, Option<&[&str]>)] = &[
+    ("package", Some(&["name", "description", "version", "license", "homepage", "authors"])),
+    ("scripts", Some(&["install", "uninstall", "build"])),
+    ("dependencies", None),
+    ("ignore", None),
+];
+
 #[derive(Deserialize)]
 pub struct CrumbInfo {
-    package: CrumblePackageInfo,
-    scripts: ScriptInfo,
+    pub package: CrumblePackageInfo,
+    pub scripts: ScriptInfo,
 
-    dependencies: Option<BTreeMap<String, String>>,
-    ignore: Option<BTreeMap<String, bool>>,
+    pub dependencies: Option<BTreeMap<String, String>>,
+    pub ignore: Option<BTreeMap<String, bool>>,
 }
 
 impl CrumbInfo {
-    pub fn from_file<P: AsRef<Path>>(path: P) -> std::io::Result<CrumbInfo> {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<CrumbInfo> {
         let path = path.as_ref();
-        let mut file = File::open(path)?;
+        let mut file = File::open(path).at(path)?;
 
         let mut toml_data = String::default();
-        file.read_to_string(&mut toml_data)?;
+        file.read_to_string(&mut toml_data).at(path)?;
 
-        Ok(CrumbInfo::from_string(toml_data))
+        CrumbInfo::parse(path.to_string_lossy(), toml_data)
     }
 
-    pub fn from_string<S: AsRef<str>>(data: S) -> CrumbInfo {
-        let crumb_info: CrumbInfo = from_str(data.as_ref()).unwrap(); // TODO: print error if this fails.
+    // Reads the crumb.toml out of a baked .crumb
+    pub fn from_crumb<P: AsRef<Path>>(path: P) -> Result<CrumbInfo> {
+        Ok(CrumbArchive::open(path)?.info)
+    }
 
-        if crumb_info.package.description.is_none() {
-            log::warn!("description is not set in [package]");
-        }
+    pub fn from_string<S: AsRef<str>>(data: S) -> Result<CrumbInfo> {
+        CrumbInfo::parse("crumb.toml", data)
+    }
 
-        if crumb_info.package.license.is_none() {
-            log::warn!("license is not set in [package]");
-        }
+    // what names the file in errors and warnings, toml already says which key on which line is wrong
+    pub fn parse<W: AsRef<str>, S: AsRef<str>>(what: W, data: S) -> Result<CrumbInfo> {
+        let what = what.as_ref();
+        let data = data.as_ref();
 
-        if crumb_info.package.license.is_none() {
-            log::warn!("homepage[] is not set in [package]");
-        }
+        let crumb_info: CrumbInfo = from_str(data).map_err(|err| BreadError::parse(what, err))?;
 
-        if crumb_info.package.license.is_none() {
-            log::warn!("authors[] is not set in [package]");
+        for (key, line) in CrumbInfo::unknown_keys(data) {
+            match line {
+                Some(line) => log::warn!("Unknown key `{}` in {} at line {}", key, what, line),
+                None => log::warn!("Unknown key `{}` in {}", key, what),
+            }
         }
 
+        Ok(crumb_info)
+    }
 
-        if crumb_info.scripts.install.is_none() {
-            log::warn!("install is not set in [scripts]");
-        }
+    // Keys crumb.toml doesn't know about (most likely typos) and the line they are on,
+    // serde would silently ignore them
+    pub fn unknown_keys<S: AsRef<str>>(data: S) -> Vec<(String, Option<usize>)> {
+        let data = data.as_ref();
+        let table = match from_str::<toml::Value>(data) {
+            Ok(toml::Value::Table(table)) => table,
+            _ => return vec![],
+        };
 
-        if crumb_info.scripts.uninstall.is_none() {
-            log::warn!("uninstall is not set in [scripts]");
-        }
+        let mut unknown = vec![];
+        for (section, value) in &table {
+            let known = match KNOWN_KEYS.iter().find(|(name, _)| name == section) {
+                Some((_, known)) => known,
+                None => {
+                    unknown.push((section.to_string(), key_line(data, None, section)));
+                    continue;
+                }
+            };
 
-        if crumb_info.scripts.build.is_none() {
-            log::warn!("build is not set in [scripts]");
+            if let (Some(known), toml::Value::Table(keys)) = (known, value) {
+                for key in keys.keys().filter(|key| !known.contains(&key.as_str())) {
+                    unknown.push((format!("{}.{}", section, key), key_line(data, Some(section), key)));
+                }
+            }
         }
 
-        if crumb_info.dependencies.is_none() {
-            log::warn!("No [dependencies] are being used");
-        }
+        unknown
+    }
+}
+
+// the line (starting at 1) on which key is set inside of [section], None for the top level
+pub fn key_line(data: &str, section: Option<&str>, key: &str) -> Option<usize> {
+    let mut current = None;
+
+    for (i, line) in data.lines().enumerate() {
+        let line = line.trim();
+
+        if line.starts_with('[') {
+            let name = line.trim_start_matches('[').split(']').next().unwrap_or_default().trim();
+            if section.is_none() && name == key {
+                return Some(i + 1);
+            }
 
-        if crumb_info.ignore.is_none() {
-            log::warn!("[ignore] is not set");
+            current = Some(name);
+            continue;
         }
 
-        crumb_info
+        let name = line.split('=').next().unwrap_or_default().trim().trim_matches('"');
+        if line.contains('=') && name == key && current == section {
+            return Some(i + 1);
+        }
     }
+
+    None
 }
 
 pub struct Crumb;
 
 impl Crumb {
-    pub fn bake_package<P: AsRef<Path>>(path: P) {
-        let path = path.as_ref();
+    // Bakes the package directory at path into {name}@{version}.crumb next to it.
+    // [scripts].build gets BREAD_PKG_NAME, BREAD_VERSION, DESTDIR (path/source) and JOBS,
+    // baking stops if it fails
+    pub fn bake_package<P: AsRef<Path>>(path: P) -> Result<PathBuf> {
+        Crumb::bake_package_at(path, source_date_epoch())
+    }
 
-        log::trace!("Reading {}", pkg_name("crumb.toml"));
-        let crumb_info = CrumbInfo::from_file(path.join("crumb.toml"));
-        if crumb_info.is_err() {
-            let err = crumb_info.err().unwrap();
+    // Like bake_package, with mtime as the time of every file in the crumb instead of
+    // SOURCE_DATE_EPOCH. the build script gets it as SOURCE_DATE_EPOCH
+    pub fn bake_package_at<P: AsRef<Path>>(path: P, mtime: Option<u64>) -> Result<PathBuf> {
+        let path = path.as_ref();
 
-            log::error!("Failed to read crumb.toml {}", err);
-            std::process::exit(1);
+        // unknown keys are already reported while parsing, `bread lint` fails on errors
+        for lint in lint_package(path, None)?.iter().filter(|lint| lint.rule != "unknown-key") {
+            match lint.severity {
+                Severity::Error => log::error!("{}", lint.to_text("crumb.toml")),
+                Severity::Warning => log::warn!("{}", lint.to_text("crumb.toml")),
+            }
         }
 
-        let info = crumb_info.unwrap();
+        log::trace!("Reading {}", pkg_name("crumb.toml"));
+        let info = CrumbInfo::from_file(path.join("crumb.toml"))?;
 
         let mut package_name = String::default();
         package_name += info.package.name.as_str();
@@ -118,67 +192,644 @@ impl Crumb {
         package_name += info.package.version.to_string().as_str();
         package_name += ".crumb";
 
-        if info.scripts.build.is_some() {
-            let build_script = info.scripts.build.unwrap();
-            log::trace!("Executing {}", pkg_name(&build_script));
+        // the build runs inside of the package directory and installs into source/,
+        // which becomes the root of the crumb
+        if let Some(build_script) = &info.scripts.build {
+            let path = fs::canonicalize(path).at(path)?;
+            let source_path = path.join("source");
+            fs::create_dir_all(&source_path).at(&source_path)?;
+
+            let mut env = vec![
+                ("BREAD_PKG_NAME", info.package.name.clone()),
+                ("BREAD_VERSION", info.package.version.to_string()),
+                ("DESTDIR", source_path.to_string_lossy().to_string()),
+                ("JOBS", num_cpus::get().to_string()),
+            ];
+
+            if let Some(mtime) = mtime {
+                env.push(("SOURCE_DATE_EPOCH", mtime.to_string()));
+            }
+
+            log::info!("Building {}@{}", pkg_name(&info.package.name), info.package.version);
+            run_script(build_script, &path, &env)?;
+        }
+
+        let crumb_path = path.join(&package_name);
+
+        // source/ becomes payload/, everything else of the package directory goes into .CRUMB/
+        let mut payload = vec![];
+        let source_path = path.join("source");
+        if source_path.is_dir() {
+            log::trace!("Attaching {}", pkg_name("source"));
+            walk(&source_path, Path::new(""), &mut payload)?;
+        }
+
+        log::trace!("Generating {}", pkg_name(MANIFEST_NAME));
+        let manifest = Manifest::from_files(&payload)?.serialize();
 
-            Command::new("sh")
-                .arg("-c")
-                .arg(format!("cd {}", path.to_str().unwrap()))
-                .arg("&&")
-                .arg(build_script)
-                .output()
-                .expect("failed to execute process");
+        let mut metadata = vec![];
+        let to_ignore = info.ignore.unwrap_or_default();
+        for (name, file) in sorted_dir(path)? {
+            let name_str = name.to_string_lossy();
+
+            // older crumbs of the package aren't part of it
+            if name_str.ends_with(".crumb") || name_str == "source" || name_str == MANIFEST_NAME || to_ignore.contains_key(name_str.as_ref()) {
+                continue;
+            }
+
+            log::trace!("Attaching {}", pkg_name(&name_str));
+            metadata.push((name.clone(), file.clone()));
+
+            if fs::symlink_metadata(&file).at(&file)?.is_dir() {
+                walk(&file, &name, &mut metadata)?;
+            }
         }
 
         log::trace!("Opening {}", pkg_name(&package_name));
-        let tar_gz = File::create(path.join(&package_name)).unwrap(); // TODO: check for error
+        let tar_gz = File::create(&crumb_path).at(&crumb_path)?;
 
-        let gz = GzEncoder::new(tar_gz, Compression::best());
+        // no file name or time in the gzip header, so the same files always give the same crumb
+        let gz = GzBuilder::new().mtime(0).write(tar_gz, Compression::best());
         let mut tar = TarBuilder::new(gz);
 
-        log::trace!("Attaching {} if possible", pkg_name("source"));
-        let appended = tar.append_dir_all("/", path.join("source"));
-        if appended.is_err() {
-            log::trace!("{}", pkg_name("Failed"));
+        let metadata_dir = Path::new(METADATA_DIR);
+        let payload_dir = Path::new(PAYLOAD_DIR);
+
+        append_generated(&mut tar, metadata_dir, EntryType::Directory, &[], mtime).at(&crumb_path)?;
+        append_generated(&mut tar, &metadata_dir.join("VERSION"), EntryType::Regular, format!("{}\n", FORMAT_VERSION).as_bytes(), mtime).at(&crumb_path)?;
+        append_generated(&mut tar, &metadata_dir.join(MANIFEST_NAME), EntryType::Regular, manifest.as_bytes(), mtime).at(&crumb_path)?;
+
+        for (name, file) in &metadata {
+            append(&mut tar, &metadata_dir.join(name), file, mtime)?;
+        }
+
+        append_generated(&mut tar, payload_dir, EntryType::Directory, &[], mtime).at(&crumb_path)?;
+        for (name, file) in &payload {
+            append(&mut tar, &payload_dir.join(name), file, mtime)?;
         }
 
-        let dir = path.read_dir().unwrap(); // TODO: check for error
+        tar.into_inner().and_then(|gz| gz.finish()).at(&crumb_path)?;
+
+        log::info!("{} created at {}", pkg_name(package_name), pkg_name(path.to_string_lossy()));
+
+        Ok(crumb_path)
+    }
+
+    // Bakes the package twice and compares both crumbs, the second one is kept.
+    // without SOURCE_DATE_EPOCH the modification time of crumb.toml is used
+    pub fn verify_reproducible<P: AsRef<Path>>(path: P) -> Result<PathBuf> {
+        let path = path.as_ref();
+
+        let mtime = match source_date_epoch() {
+            Some(mtime) => mtime,
+            None => {
+                let crumb_toml = path.join("crumb.toml");
+                let mtime = fs::metadata(&crumb_toml).at(&crumb_toml)?.modified().at(&crumb_toml)?
+                    .duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+
+                log::info!("SOURCE_DATE_EPOCH is not set, using {} from crumb.toml", mtime);
+                mtime
+            }
+        };
+
+        let first = Crumb::bake_package_at(path, Some(mtime))?;
+        let name = first.file_name().unwrap_or_default().to_string_lossy().to_string();
+
+        // moved away, otherwise the second bake would include it
+        let first_copy = std::env::temp_dir().join(format!("bread-reproducible-{}", name));
+        fs::copy(&first, &first_copy).at(&first_copy)?;
+        fs::remove_file(&first).at(&first)?;
+
+        let second = Crumb::bake_package_at(path, Some(mtime))?;
+        let differences = crumb_differences(&first_copy, &second);
+        let _ = fs::remove_file(&first_copy);
+
+        let differences = differences?;
+        if !differences.is_empty() {
+            return Err(BreadError::NotReproducible { name, differences });
+        }
+
+        log::info!("{} is reproducible", pkg_name(&name));
+        Ok(second)
+    }
+
+    // Resolves the given packages against the saved databases and downloads
+    // them with their dependencies into the cache, in install order.
+    // dependencies already installed in root are left out
+    pub async fn cache_package<S: AsRef<str>, R: AsRef<Path>>(names: &[S], root: R, insecure: bool) -> Result<Vec<PathBuf>> {
+        let databases = Crumb::load_databases()?;
+        let plan = resolve(&databases, names)?;
+
+        let installed = InstalledPackage::load_all(root)?;
+        let entries = to_install(plan, &installed, names);
+
+        for entry in &entries {
+            log::trace!("Planned {}@{}", pkg_name(&entry.name), entry.version);
+        }
+
+        Crumb::download_entries(&databases, &entries, insecure).await
+    }
+
+    // The saved databases, fails if `bread update` never ran
+    pub fn load_databases() -> Result<Vec<Database>> {
+        let databases_path = Path::new(PATH_CONFIGS).join("databases");
+        if !databases_path.is_dir() {
+            return Err(BreadError::Config(format!("There are no databases in {}, try running `bread update`", databases_path.display())));
+        }
+
+        Database::load_all(&databases_path)
+    }
+
+    // Downloads the crumbs of entries into the cache and verifies them,
+    // each one from the mirrors of the database it comes from
+    pub async fn download_entries(databases: &[Database], entries: &[DatabaseEntry], insecure: bool) -> Result<Vec<PathBuf>> {
+        fs::create_dir_all(PATH_CACHE).at(PATH_CACHE)?;
+
+        let config = MirrorConfig::load(PATH_CONFIGS)?;
+        let settings = Config::load(PATH_CONFIGS)?;
+        let keyring = system_keyring(insecure)?;
+        let downloader = Downloader::new(&settings.network)?;
+
+        let mut pool = MirrorPool::new(&config, settings.settings.region, Path::new(PATH_CACHE).join("mirrors.toml"), keyring, downloader);
+
+        // the database each entry comes from, which decides the mirrors to use, and the
+        // mirror it's downloaded from first. the ranking changes while the results come in
+        let mut entry_databases = vec![];
+        let mut entry_mirrors = vec![];
+        let mut uris = vec![];
+        for entry in entries {
+            let database = databases.iter()
+                .find(|db| db.entries.contains(entry))
+                .map(|db| db.name.clone())
+                .unwrap_or_default();
+
+            let mirror = match pool.ranked(&database).first() {
+                Some(mirror) => (*mirror).clone(),
+                None => return Err(BreadError::Config(format!("No mirror is declared for {}, the database of {}", database, entry.name))),
+            };
 
-        let mut to_ignore = BTreeMap::new();
-        if info.ignore.is_some() {
-            to_ignore = info.ignore.unwrap();
+            uris.push(mirror.crumb_uri(entry));
+            entry_databases.push(database);
+            entry_mirrors.push(mirror);
         }
 
-        for file in dir {
-            let file = file.unwrap();
-            let file_name_os = file.file_name();
-            let file_name = file_name_os.to_str().unwrap();
+        // everything is downloaded from the best mirror at once,
+        // what failed is tried again one by one from the other mirrors
+        let downloads = uris.into_iter().zip(entries.iter().map(Some)).collect();
+        let results = pool.downloader.download_files(downloads).await;
 
-            if file_name == package_name {
+        let mut paths = vec![];
+        for (((entry, database), first_mirror), result) in entries.iter().zip(entry_databases).zip(entry_mirrors).zip(results) {
+            let path = Path::new(PATH_CACHE).join(entry.file_name());
+
+            let verified = match result {
+                Ok(_) => pool.verify_signature(&first_mirror, entry, &path).await,
+                Err(err) => Err(err.into()),
+            };
+
+            if let Err(err) = verified {
+                log::warn!("{}", err);
+            } else {
+                pool.record_success(&first_mirror, None);
+                paths.push(path);
                 continue;
             }
 
-            log::trace!("Attaching {}", pkg_name(file_name));
+            log::warn!("Trying the other mirrors for {}", pkg_name(entry.file_name()));
+            let _ = fs::remove_file(&path);
+            pool.record_failure(&first_mirror);
 
-            if !to_ignore.contains_key(file_name) {
-                tar.append_path_with_name(file.path(), file_name).unwrap();
+            match pool.download_crumb(&database, entry, &[first_mirror]).await {
+                Ok(path) => paths.push(path),
+                Err(err) => {
+                    log::error!("Failed to download {} from any mirror", pkg_name(entry.file_name()));
+                    pool.save()?;
+                    return Err(err);
+                }
             }
         }
 
-        log::info!("{} created at {}", pkg_name(package_name), pkg_name(path.to_str().unwrap()));
+        pool.save()?;
+
+        Ok(paths)
+    }
+
+    // Sum of the sizes of everything inside of a .crumb
+    pub fn installed_size<P: AsRef<Path>>(path: P) -> Result<u64> {
+        CrumbArchive::open(path)?.payload_size()
+    }
+
+    // Unpacks the payload of a .crumb into PATH_INSTALL, runs [scripts].install
+    // and keeps the metadata at /etc/bread/installed/{name}/
+    pub async fn install_package<P: AsRef<Path>>(path: P) -> Result<()> {
+        Crumb::install_package_in(path, PATH_INSTALL).await
     }
 
-    pub async fn cache_package<S: AsRef<str>>(name: S) -> Option<File> {
-        //let db = crate::database::Database::from_mirror()
+    // Like install_package, but into another root, which is what `bread strip` does
+    pub async fn install_package_in<P: AsRef<Path>, R: AsRef<Path>>(path: P, root: R) -> Result<()> {
+        let path = path.as_ref();
+        let root = root.as_ref();
+
+        log::trace!("Reading the metadata of {}", pkg_name(path.to_string_lossy()));
+        let crumb = CrumbArchive::open(path)?;
+
+        let installed_path = crumb.installed_path(root)?;
+        fs::create_dir_all(&installed_path).at(&installed_path)?;
+
+        log::info!("Installing {}@{}", pkg_name(&crumb.info.package.name), crumb.info.package.version);
+
+        let mut metadata = Extractor::new(&crumb, &installed_path)?;
+        let mut payload = Extractor::new(&crumb, root)?;
+
+        let mut archive = CrumbArchive::tar(path)?;
+        for entry in archive.entries().at(path)? {
+            let mut entry = entry.at(path)?;
+
+            match crumb.classify(entry.path().at(path)?) {
+                EntryKind::Metadata(name) => {
+                    metadata.unpack(&mut entry, name)?;
+                }
+
+                EntryKind::Payload(name) => {
+                    log::trace!("Unpacking {}", pkg_name(name.to_string_lossy()));
+                    payload.unpack(&mut entry, name)?;
+                }
+
+                EntryKind::Unsafe(name) => return Err(BreadError::Unsafe {
+                    crumb: path.display().to_string(),
+                    entry: name.display().to_string(),
+                    reason: "it points outside of the root".to_string(),
+                }),
+
+                EntryKind::Skip => {}
+            }
+        }
+
+        let info = crumb.info;
+        if let Some(install_script) = info.scripts.install {
+            run_script(&install_script, &installed_path, &[("BREAD_ROOT", root.to_string_lossy().to_string())])?;
+        }
+
+        Ok(())
+    }
+
+    // Removes installed packages from root, the ones depending on them go first.
+    // fails if anything else still depends on them, unless cascade removes those too
+    pub fn remove_packages<S: AsRef<str>, R: AsRef<Path>>(names: &[S], root: R, cascade: bool) -> Result<()> {
+        let root = root.as_ref();
+        let installed = InstalledPackage::load_all(root)?;
+
+        for name in names {
+            if !installed.iter().any(|package| package.name() == name.as_ref()) {
+                return Err(BreadError::NotInstalled { name: name.as_ref().to_string() });
+            }
+        }
+
+        let mut to_remove: Vec<String> = names.iter().map(|name| name.as_ref().to_string()).collect();
+        if cascade {
+            to_remove.extend(dependents(&installed, names, true));
+        } else {
+            for name in names {
+                let direct: Vec<String> = dependents(&installed, &[name], false).into_iter()
+                    .filter(|dependent| !to_remove.contains(dependent))
+                    .collect();
+
+                if !direct.is_empty() {
+                    return Err(BreadError::Dependents { name: name.as_ref().to_string(), dependents: direct });
+                }
+            }
+        }
+
+        let mut remaining: Vec<&InstalledPackage> = installed.iter()
+            .filter(|package| to_remove.iter().any(|name| name == package.name()))
+            .collect();
+
+        let mut removed = vec![];
+        while !remaining.is_empty() {
+            // nothing that's left depends on it, a dependency cycle just goes in order
+            let next = remaining.iter()
+                .position(|package| !remaining.iter().any(|other| other.depends_on(package.name())))
+                .unwrap_or(0);
 
-        None
+            let package = remaining.remove(next);
+            let others: Vec<&InstalledPackage> = installed.iter()
+                .filter(|other| other.name() != package.name() && !removed.contains(&other.name()))
+                .collect();
+
+            Crumb::remove_package(package, &others, root)?;
+            removed.push(package.name());
+        }
+
+        Ok(())
     }
 
-    pub async fn install_package<P: AsRef<Path>>(path: P) {
+    // runs [scripts].uninstall, deletes the files of the manifest nothing else owns
+    // and forgets about the package. modified files in etc/ are kept
+    fn remove_package(package: &InstalledPackage, others: &[&InstalledPackage], root: &Path) -> Result<()> {
+        log::info!("Removing {}@{}", pkg_name(package.name()), package.info.package.version);
+
+        if let Some(uninstall_script) = &package.info.scripts.uninstall {
+            run_script(uninstall_script, &package.path, &[("BREAD_ROOT", root.to_string_lossy().to_string())])?;
+        }
+
+        let entries = match &package.manifest {
+            Some(manifest) => manifest.entries.as_slice(),
+            None => {
+                log::warn!("{} has no manifest, its files are left in place", pkg_name(package.name()));
+                &[]
+            }
+        };
+
+        let pb = ProgressBar::new(entries.len() as u64);
+        pb.set_style(uninstall_pg_style());
+        pb.set_prefix(&format!("[{}]", package.name()));
+
+        // children go before their parents, so directories are empty once we get to them
+        for entry in entries.iter().rev() {
+            pb.inc(1);
+
+            let relative = match normalize(Path::new(&entry.path)) {
+                Some(relative) if !relative.as_os_str().is_empty() => relative,
+                _ => {
+                    log::warn!("{} is outside of the root, it's left in place", entry.path);
+                    continue;
+                }
+            };
+
+            let path = root.join(&relative);
+            let metadata = match fs::symlink_metadata(&path) {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+
+            // shared with other packages, like usr/bin
+            if entry.file_type == FileType::Directory {
+                if metadata.is_dir() {
+                    let _ = fs::remove_dir(&path);
+                }
+
+                continue;
+            }
+
+            if others.iter().any(|other| other.manifest.as_ref().and_then(|manifest| manifest.get(&entry.path)).is_some()) {
+                log::trace!("{} belongs to another package too", pkg_name(&entry.path));
+                continue;
+            }
+
+            if metadata.is_dir() {
+                log::warn!("{} is a directory now, it's left in place", path.display());
+                continue;
+            }
+
+            if relative.starts_with("etc") && entry.file_type == FileType::File {
+                let current = ManifestEntry::from_file(&entry.path, &path)?.and_then(|current| current.sha512);
+
+                if current != entry.sha512 {
+                    log::warn!("Keeping {}, it was modified", path.display());
+                    continue;
+                }
+            }
+
+            fs::remove_file(&path).at(&path)?;
+        }
+
+        pb.finish_and_clear();
+
+        fs::remove_dir_all(&package.path).at(&package.path)
     }
 }
 
+// the entries of plan which have to be installed: the requested ones, and dependencies which
+// aren't installed or whose installed version doesn't satisfy what the plan requires of them
+fn to_install<S: AsRef<str>>(plan: Vec<DatabaseEntry>, installed: &[InstalledPackage], names: &[S]) -> Vec<DatabaseEntry> {
+    let outdated = |entry: &DatabaseEntry| {
+        let version = match installed.iter().find(|package| package.name() == entry.name) {
+            Some(package) => &package.info.package.version,
+            None => return true,
+        };
+
+        let satisfied = plan.iter()
+            .filter_map(|dependent| dependent.dependencies.get(&entry.name))
+            .all(|requirement| VersionReq::parse(requirement).map_or(false, |requirement| requirement.matches(version)));
+
+        if !satisfied {
+            log::info!("{}@{} is installed, {} is needed", pkg_name(&entry.name), version, entry.version);
+        }
+
+        !satisfied
+    };
+
+    plan.iter()
+        .filter(|entry| names.iter().any(|name| name.as_ref() == entry.name) || outdated(entry))
+        .cloned()
+        .collect()
+}
+
+// Runs a script of [scripts] with sh inside of cwd, its output is logged line by line
+// while it runs. fails if it doesn't exit with 0
+fn run_script<P: AsRef<Path>>(script: &str, cwd: P, envs: &[(&str, String)]) -> Result<()> {
+    log::trace!("Executing {}", pkg_name(script));
+
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(script)
+        .current_dir(cwd.as_ref())
+        .envs(envs.iter().map(|(key, value)| (key, value)))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .at(cwd.as_ref())?;
+
+    fn log_lines<R: Read>(output: R) {
+        let mut output = BufReader::new(output);
+        let mut line = vec![];
+
+        while output.read_until(b'\n', &mut line).unwrap_or(0) > 0 {
+            log::info!("{}", String::from_utf8_lossy(&line).trim_end());
+            line.clear();
+        }
+    }
+
+    let stderr = child.stderr.take().map(|stderr| thread::spawn(move || log_lines(stderr)));
+    if let Some(stdout) = child.stdout.take() {
+        log_lines(stdout);
+    }
+
+    if let Some(stderr) = stderr {
+        let _ = stderr.join();
+    }
+
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(BreadError::Script { script: script.to_string(), status });
+    }
+
+    Ok(())
+}
+
+// the entries of dir sorted by name, as (file name, path)
+fn sorted_dir(dir: &Path) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let mut entries = vec![];
+    for entry in dir.read_dir().at(dir)? {
+        let entry = entry.at(dir)?;
+        entries.push((PathBuf::from(entry.file_name()), entry.path()));
+    }
+
+    entries.sort();
+    Ok(entries)
+}
+
+// every file below dir in a stable order, as (path inside of the crumb, path on disk)
+fn walk(dir: &Path, prefix: &Path, files: &mut Vec<(PathBuf, PathBuf)>) -> Result<()> {
+    for (name, file) in sorted_dir(dir)? {
+        let name = prefix.join(name);
+        files.push((name.clone(), file.clone()));
+
+        if fs::symlink_metadata(&file).at(&file)?.is_dir() {
+            walk(&file, &name, files)?;
+        }
+    }
+
+    Ok(())
+}
+
+// the time every file in a crumb gets, see https://reproducible-builds.org/specs/source-date-epoch/
+pub fn source_date_epoch() -> Option<u64> {
+    std::env::var("SOURCE_DATE_EPOCH").ok()?.trim().parse().ok()
+}
+
+// a header owned by root, files bread generates get the current time without mtime
+fn root_header(mtime: Option<u64>) -> Header {
+    let mut header = Header::new_gnu();
+    header.set_uid(0);
+    header.set_gid(0);
+
+    // the names fit into the header, this can't fail
+    let _ = header.set_username("root");
+    let _ = header.set_groupname("root");
+
+    header.set_mtime(mtime.unwrap_or_else(|| {
+        std::time::SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+    }));
+
+    header
+}
+
+// appends a file or directory bread made up, like the manifest
+fn append_generated<W: Write>(tar: &mut TarBuilder<W>, name: &Path, entry_type: EntryType, data: &[u8], mtime: Option<u64>) -> io::Result<()> {
+    let mut header = root_header(mtime);
+    header.set_entry_type(entry_type);
+    header.set_mode(if entry_type == EntryType::Directory { 0o755 } else { 0o644 });
+    header.set_size(data.len() as u64);
+
+    tar.append_data(&mut header, name, data)
+}
+
+// appends a file, directory or symlink. the owner is always root,
+// with mtime set every entry gets it instead of its modification time
+fn append<W: Write>(tar: &mut TarBuilder<W>, name: &Path, path: &Path, mtime: Option<u64>) -> Result<()> {
+    let metadata = fs::symlink_metadata(path).at(path)?;
+
+    let mut header = root_header(None);
+    header.set_metadata(&metadata);
+    header.set_uid(0);
+    header.set_gid(0);
+
+    if let Some(mtime) = mtime {
+        header.set_mtime(mtime);
+    }
+
+    if metadata.file_type().is_symlink() {
+        header.set_size(0);
+        header.set_link_name(fs::read_link(path).at(path)?).at(path)?;
+        tar.append_data(&mut header, name, io::empty()).at(path)
+    } else if metadata.is_dir() {
+        header.set_size(0);
+        tar.append_data(&mut header, name, io::empty()).at(path)
+    } else if metadata.is_file() {
+        tar.append_data(&mut header, name, File::open(path).at(path)?).at(path)
+    } else {
+        log::warn!("Skipping {}, only files, directories and symlinks can be in a crumb", pkg_name(path.to_string_lossy()));
+        Ok(())
+    }
+}
+
+// the name of an entry and what can differ about it, like ("mode", "644")
+type CrumbEntry = (String, Vec<(&'static str, String)>);
+
+// what a crumb is made of, entry by entry
+fn crumb_entries(path: &Path) -> Result<Vec<CrumbEntry>> {
+    let mut archive = Archive::new(GzDecoder::new(File::open(path).at(path)?));
+
+    let mut entries = vec![];
+    for entry in archive.entries().at(path)? {
+        let mut entry = entry.at(path)?;
+        let name = entry.path().at(path)?.to_string_lossy().to_string();
+        let header = entry.header().clone();
+
+        let mut sha512 = Sha512::default();
+        io::copy(&mut entry, &mut sha512).at(path)?;
+
+        entries.push((name, vec![
+            ("type", format!("{:?}", header.entry_type())),
+            ("mode", format!("{:o}", header.mode().at(path)?)),
+            ("owner", format!("{}:{}", header.uid().at(path)?, header.gid().at(path)?)),
+            ("mtime", header.mtime().at(path)?.to_string()),
+            ("link", header.link_name().at(path)?.map(|link| link.to_string_lossy().to_string()).unwrap_or_default()),
+            ("sha512", hex::encode(sha512.result())),
+        ]));
+    }
+
+    Ok(entries)
+}
+
+// a line for every difference between two crumbs, empty if they are the same file
+fn crumb_differences(first: &Path, second: &Path) -> Result<Vec<String>> {
+    if fs::read(first).at(first)? == fs::read(second).at(second)? {
+        return Ok(vec![]);
+    }
+
+    let first_entries = crumb_entries(first)?;
+    let second_entries = crumb_entries(second)?;
+    let second_map: BTreeMap<&String, &Vec<(&str, String)>> = second_entries.iter().map(|(name, fields)| (name, fields)).collect();
+
+    let mut differences = vec![];
+    for (name, fields) in &first_entries {
+        let other = match second_map.get(name) {
+            Some(other) => other,
+            None => {
+                differences.push(format!("{} is only in the first crumb", name));
+                continue;
+            }
+        };
+
+        for ((field, value), (_, other_value)) in fields.iter().zip(other.iter()) {
+            if value != other_value {
+                differences.push(format!("{}: {} {} != {}", name, field, value, other_value));
+            }
+        }
+    }
+
+    for (name, _) in &second_entries {
+        if !first_entries.iter().any(|(first_name, _)| first_name == name) {
+            differences.push(format!("{} is only in the second crumb", name));
+        }
+    }
+
+    if differences.is_empty() {
+        let order = |entries: &Vec<(String, Vec<(&str, String)>)>| entries.iter().map(|(name, _)| name.clone()).collect::<Vec<String>>();
+
+        if order(&first_entries) != order(&second_entries) {
+            differences.push("the files are in a different order".to_string());
+        } else {
+            differences.push("the files are the same, the tar or gzip headers differ".to_string());
+        }
+    }
+
+    Ok(differences)
+}
+
 #[test]
 fn parse_crumb_info() {
     let toml_data = r#"
@@ -195,13 +846,232 @@ fn parse_crumb_info() {
         [dependencies]
     "#;
 
-    let crumb_info = CrumbInfo::from_string(toml_data);
+    let crumb_info = CrumbInfo::from_string(toml_data).unwrap();
 
     assert_eq!("linux-fs".to_string(), crumb_info.package.name);
     assert_eq!(Some("Linux Filesystem".to_string()), crumb_info.package.description);
-    assert_eq!(1.0, crumb_info.package.version);
+    assert_eq!("1.0".parse::<Version>().unwrap(), crumb_info.package.version);
 
     assert_eq!(Some("./install.sh".to_string()), crumb_info.scripts.install);
     assert_eq!(Some("./uninstall.sh".to_string()), crumb_info.scripts.uninstall);
     assert_eq!(Some("./build.sh".to_string()), crumb_info.scripts.build);
 }
+
+#[test]
+fn crumb_info_errors() {
+    let err = CrumbInfo::from_string("[package]\nname = \"linux-fs\"\nversion = 1\n\n[scripts]\n").err().unwrap();
+    assert_eq!(65, err.exit_code());
+    assert!(err.to_string().starts_with("Failed to parse crumb.toml: "));
+    assert!(err.to_string().contains("for key `package.version` at line 3"), "{}", err);
+
+    let path = std::env::temp_dir().join("bread-crumb-info-errors.toml");
+    fs::write(&path, "[package]\nname = \"linux-fs\"\n").unwrap();
+    let err = CrumbInfo::from_file(&path).err().unwrap();
+    assert!(err.to_string().contains(path.to_str().unwrap()), "{}", err);
+    assert!(err.to_string().contains("missing field `version`"), "{}", err);
+    fs::remove_file(&path).unwrap();
+
+    let toml_data = r#"
+        [package]
+        name    = "linux-fs"
+        version = "1.0"
+        homepag = ["https://leopard.mempler.de"]
+
+        [scripts]
+        instal = "./install.sh"
+
+        [dependencies]
+        glibc = "^2.31"
+
+        [maintainer]
+        name = "mempler"
+    "#;
+
+    assert!(CrumbInfo::from_string(toml_data).is_ok());
+    assert_eq!(vec![
+        ("maintainer".to_string(), Some(13)),
+        ("package.homepag".to_string(), Some(5)),
+        ("scripts.instal".to_string(), Some(8)),
+    ], CrumbInfo::unknown_keys(toml_data));
+}
+
+#[test]
+fn bake_runs_build_script() {
+    let path = std::env::temp_dir().join("bread-bake-runs-build-script");
+    let _ = fs::remove_dir_all(&path);
+    fs::create_dir_all(&path).unwrap();
+
+    let crumb_toml = r#"
+        [package]
+        name    = "hello"
+        version = "1.0"
+
+        [scripts]
+        build = "test -f crumb.toml && mkdir -p $DESTDIR/usr/share && echo $BREAD_PKG_NAME@$BREAD_VERSION $JOBS > $DESTDIR/usr/share/hello"
+    "#;
+
+    fs::write(path.join("crumb.toml"), crumb_toml).unwrap();
+    let crumb = Crumb::bake_package(&path).unwrap();
+    assert_eq!(path.join("hello@1.0.crumb"), crumb);
+
+    let hello = fs::read_to_string(path.join("source/usr/share/hello")).unwrap();
+    assert_eq!(format!("hello@1.0 {}\n", num_cpus::get()), hello);
+
+    fs::write(path.join("crumb.toml"), crumb_toml.replace("test -f crumb.toml", "false")).unwrap();
+    let err = Crumb::bake_package(&path).err().unwrap();
+    assert_eq!(70, err.exit_code());
+
+    fs::remove_dir_all(&path).unwrap();
+}
+
+#[test]
+fn reproducible_crumbs() {
+    let path = std::env::temp_dir().join("bread-reproducible-crumbs");
+    let _ = fs::remove_dir_all(&path);
+    fs::create_dir_all(path.join("source/usr/bin")).unwrap();
+    fs::write(path.join("source/usr/bin/hello"), "#!/bin/sh\necho hello\n").unwrap();
+
+    let crumb_toml = r#"
+        [package]
+        name    = "hello"
+        version = "1.0"
+
+        [scripts]
+        build = "touch $DESTDIR/usr/bin/hello"
+    "#;
+
+    fs::write(path.join("crumb.toml"), crumb_toml).unwrap();
+    let crumb = Crumb::verify_reproducible(&path).unwrap();
+
+    let entries = crumb_entries(&crumb).unwrap();
+    assert_eq!(
+        vec![".CRUMB", ".CRUMB/VERSION", ".CRUMB/manifest.toml", ".CRUMB/crumb.toml", "payload", "payload/usr", "payload/usr/bin", "payload/usr/bin/hello"],
+        entries.iter().map(|(name, _)| name.as_str()).collect::<Vec<&str>>()
+    );
+    assert!(entries.iter().all(|(_, fields)| fields[2].1 == "0:0"));
+
+    let archive = CrumbArchive::open(&crumb).unwrap();
+    assert_eq!(FORMAT_VERSION, archive.format);
+    assert_eq!("hello", archive.info.package.name);
+    assert!(archive.manifest.as_ref().unwrap().get("usr/bin/hello").is_some());
+    assert_eq!(crumb_toml.as_bytes(), &archive.read_metadata("crumb.toml").unwrap()[..]);
+
+    fs::write(path.join("crumb.toml"), crumb_toml.replace("touch", "date +%N >")).unwrap();
+    match Crumb::verify_reproducible(&path) {
+        Err(BreadError::NotReproducible { differences, .. }) => assert!(differences.iter().any(|difference| difference.starts_with("payload/usr/bin/hello: sha512 ")), "{:?}", differences),
+        _ => panic!("hello should not be reproducible"),
+    }
+
+    fs::remove_dir_all(&path).unwrap();
+}
+
+#[test]
+fn install_and_remove() {
+    use crate::installed::installed_dir;
+
+    let path = std::env::temp_dir().join("bread-install-and-remove");
+    let root = path.join("root");
+    let _ = fs::remove_dir_all(&path);
+    fs::create_dir_all(path.join("source/usr/bin")).unwrap();
+    fs::create_dir_all(path.join("source/etc")).unwrap();
+    fs::write(path.join("source/usr/bin/hello"), "#!/bin/sh\necho hello\n").unwrap();
+    fs::write(path.join("source/etc/hello.conf"), "greeting = hello\n").unwrap();
+    fs::write(path.join("crumb.toml"), "[package]\nname = \"hello\"\nversion = \"1.0\"\n[scripts]\nuninstall = \"touch $BREAD_ROOT/uninstalled\"\n").unwrap();
+
+    let crumb = Crumb::bake_package(&path).unwrap();
+    futures::executor::block_on(Crumb::install_package_in(&crumb, &root)).unwrap();
+    assert!(root.join("usr/bin/hello").is_file());
+    assert!(installed_dir(&root).join("hello").join(MANIFEST_NAME).is_file());
+
+    let greeter = installed_dir(&root).join("greeter");
+    fs::create_dir_all(&greeter).unwrap();
+    fs::write(greeter.join("crumb.toml"), "[package]\nname = \"greeter\"\nversion = \"1.0\"\n[scripts]\n[dependencies]\nhello = \"^1.0\"\n").unwrap();
+
+    match Crumb::remove_packages(&["hello"], &root, false) {
+        Err(BreadError::Dependents { dependents, .. }) => assert_eq!(vec!["greeter"], dependents),
+        _ => panic!("greeter depends on hello"),
+    }
+
+    assert!(matches!(Crumb::remove_packages(&["hi"], &root, false), Err(BreadError::NotInstalled { .. })));
+
+    fs::write(root.join("etc/hello.conf"), "greeting = hi\n").unwrap();
+    Crumb::remove_packages(&["hello"], &root, true).unwrap();
+
+    assert!(!root.join("usr").exists());
+    assert!(root.join("etc/hello.conf").is_file());
+    assert!(root.join("uninstalled").is_file());
+    assert!(InstalledPackage::load_all(&root).unwrap().is_empty());
+
+    // a crumb named .. would keep its metadata in etc/bread and run its script there
+    fs::write(path.join("crumb.toml"), "[package]\nname = \"..\"\nversion = \"1.0\"\n[scripts]\ninstall = \"touch escaped\"\n").unwrap();
+    let escaping = Crumb::bake_package(&path).unwrap();
+    assert!(matches!(futures::executor::block_on(Crumb::install_package_in(&escaping, &root)), Err(BreadError::Unsafe { .. })));
+    assert!(!installed_dir(&root).join("../crumb.toml").exists());
+    assert!(!installed_dir(&root).join("../escaped").exists());
+
+    fs::remove_dir_all(&path).unwrap();
+}
+
+#[test]
+fn install_escaping_entries() {
+    let path = std::env::temp_dir().join("bread-install-escaping-entries");
+    let root = path.join("root");
+    let _ = fs::remove_dir_all(&path);
+    fs::create_dir_all(&path).unwrap();
+
+    // Header::set_path refuses .. so the names are written by hand
+    let crumb = path.join("evil@1.0.crumb");
+    let mut tar = TarBuilder::new(GzBuilder::new().write(File::create(&crumb).unwrap(), Compression::default()));
+    for (name, data) in &[
+        (".CRUMB/VERSION", "1\n"),
+        (".CRUMB/crumb.toml", "[package]\nname = \"evil\"\nversion = \"1.0\"\n[scripts]\n"),
+        ("payload/usr/bin/evil", ""),
+        ("payload/../escaped", ""),
+    ] {
+        let mut header = Header::new_gnu();
+        header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+        header.set_entry_type(EntryType::Regular);
+        header.set_mode(0o644);
+        header.set_size(data.len() as u64);
+        header.set_cksum();
+        tar.append(&header, data.as_bytes()).unwrap();
+    }
+    tar.into_inner().unwrap().finish().unwrap();
+
+    match futures::executor::block_on(Crumb::install_package_in(&crumb, &root)) {
+        Err(BreadError::Unsafe { entry, .. }) => assert_eq!("payload/../escaped", entry),
+        _ => panic!("payload/../escaped should have been refused"),
+    }
+
+    assert!(!path.join("escaped").exists());
+
+    fs::remove_dir_all(&path).unwrap();
+}
+
+#[test]
+fn install_outdated_dependencies() {
+    let entry = |name: &str, version: &str, dependencies: &[(&str, &str)]| DatabaseEntry {
+        name: name.to_string(),
+        version: version.parse().unwrap(),
+        dependencies: dependencies.iter().map(|(n, r)| (n.to_string(), r.to_string())).collect(),
+        ..DatabaseEntry::new()
+    };
+
+    let installed: Vec<InstalledPackage> = [("libc", "1.0"), ("acl", "2.2")].iter()
+        .map(|(name, version)| InstalledPackage {
+            path: Path::new("/etc/bread/installed").join(name),
+            info: CrumbInfo::from_string(format!("[package]\nname = \"{}\"\nversion = \"{}\"\n[scripts]\n", name, version)).unwrap(),
+            manifest: None,
+        })
+        .collect();
+
+    let plan = vec![
+        entry("libc", "1.2", &[]),
+        entry("acl", "2.2.53", &[]),
+        entry("zlib", "1.2", &[]),
+        entry("coreutils", "8.32", &[("libc", ">=1.1"), ("acl", "^2.2"), ("zlib", "*")]),
+    ];
+
+    let names: Vec<String> = to_install(plan, &installed, &["coreutils"]).into_iter().map(|entry| entry.name).collect();
+    assert_eq!(vec!["libc", "zlib", "coreutils"], names);
+}
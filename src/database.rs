@@ -1,16 +1,33 @@
 // a bread database is super simple, basically
 // $NAME@$VERSION $BYTE_SIZE $FILE_SHA512
+//
+// a version never contains an @, so the last @ separates the name from the version.
+// names may not contain whitespace. blank lines and lines starting with # are skipped.
+//
+// since VERSION_2_0 an entry may be followed by indented fields
+// which belong to it, one per line. unknown fields are ignored.
+// a backslash, line breaks, tabs and whitespace at either end of a value are escaped
+// as \\, \n, \r, \t, \s (a space) or \u{hex}, so every value comes back as it was.
+//     description $TEXT
+//     license $LICENSE
+//     architecture $ARCH
+//     installed_size $BYTE_SIZE
+//     build_date $UNIX_TIMESTAMP
+//     depends $NAME $REQUIREMENT      (once per dependency)
+//
 // it is required to be a .gz
 // a file name would be (database_name).db.gz
 // the database would be extracted on the disk at /var/bread/database/{database_name}.db
 
 use std::fs;
-use std::path::Path;
-use std::time::Instant;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::collections::btree_map::BTreeMap;
+use std::time::{Instant, UNIX_EPOCH};
 
 use sha2::{Sha512, Digest};
 
-use log::{trace, info, error};
+use log::{trace, info};
 
 use flate2::Compression;
 use flate2::write::GzEncoder;
@@ -19,79 +36,286 @@ use flate2::read::GzDecoder;
 use indicatif::{ProgressBar, MultiProgress, TickTimeLimit, ProgressDrawTarget};
 
 use crate::style::{pkg_name, install_pg_style};
-use std::io::{Read, Write};
-use std::cmp::Ordering;
-
-#[derive(Debug, Clone)]
+use crate::version::{Version, ParseVersionError};
+use crate::crumb::{Crumb, CrumbInfo};
+use crate::constants::PATH_CACHE;
+use crate::signature::{Keyring, signature_path};
+use crate::net::Downloader;
+use crate::error::{BreadError, IoContext, Result};
+
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct DatabaseEntry {
     pub name: String,
-    pub version: String,
+    pub version: Version,
 
     pub size: u64,
     pub checksum: String, 
+
+    // name -> version requirement, as in [dependencies] of a crumb.toml
+    pub dependencies: BTreeMap<String, String>,
+
+    // those are only known since VERSION_2_0
+    pub description: Option<String>,
+    pub license: Option<String>,
+    pub architecture: Option<String>,
+    pub installed_size: Option<u64>,
+    pub build_date: Option<u64>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Database {
     pub name: String,
 
     pub entries: Vec<DatabaseEntry>
 }
 
+fn escape(value: &str) -> String {
+    let last = value.chars().count().saturating_sub(1);
+
+    let mut escaped = String::default();
+    for (i, c) in value.chars().enumerate() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            ' ' if i == 0 || i == last => escaped.push_str("\\s"),
+            c if c.is_whitespace() && (i == 0 || i == last) => escaped.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+// unknown escapes are kept as they are
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::default();
+    let mut rest = value;
+
+    while let Some(i) = rest.find('\\') {
+        unescaped.push_str(&rest[..i]);
+        rest = &rest[i..];
+
+        let (c, len) = match rest[1..].chars().next() {
+            Some('\\') => (Some('\\'), 2),
+            Some('n') => (Some('\n'), 2),
+            Some('r') => (Some('\r'), 2),
+            Some('t') => (Some('\t'), 2),
+            Some('s') => (Some(' '), 2),
+            Some('u') => match (rest.starts_with("\\u{"), rest.find('}')) {
+                (true, Some(end)) => (u32::from_str_radix(&rest[3..end], 16).ok().and_then(std::char::from_u32), end + 1),
+                _ => (None, 1),
+            },
+            _ => (None, 1),
+        };
+
+        match c {
+            Some(c) => unescaped.push(c),
+            None => unescaped.push_str(&rest[..len]),
+        }
+
+        rest = &rest[len..];
+    }
+
+    unescaped.push_str(rest);
+    unescaped
+}
+
+// splits $NAME@$VERSION at the last @
+fn split_name_version(data: &str) -> Option<(&str, &str)> {
+    let i = data.rfind('@')?;
+
+    Some((&data[..i], &data[i + 1..]))
+}
+
 impl DatabaseEntry {
-    pub async fn from_file<P: AsRef<Path>>(path: P) -> DatabaseEntry {
+    pub async fn from_file<P: AsRef<Path>>(path: P) -> Result<DatabaseEntry> {
         let path = path.as_ref();
-        let mut file = fs::File::open(path).unwrap(); // TODO: check for permissions
-        let meta = file.metadata().unwrap(); // TODO: check for permissions
+        let mut file = fs::File::open(path).at(path)?;
+        let meta = file.metadata().at(path)?;
 
         let mut sha512 = Sha512::default();
-        std::io::copy(&mut file, &mut sha512).unwrap();
+        io::copy(&mut file, &mut sha512).at(path)?;
         let hash = hex::encode(sha512.result());
 
-        let file_name = path.file_name().unwrap().to_str().unwrap();
-        let file_name_s: Vec<&str> = file_name.split("@").collect();
+        let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+        let (name, version) = split_name_version(file_name.trim_end_matches(".crumb"))
+            .ok_or_else(|| BreadError::parse(file_name, "a crumb has to be named $NAME@$VERSION.crumb"))?;
+
+        let version = version.parse().map_err(|err| BreadError::parse(file_name, err))?;
 
-        let name = file_name_s[0]; // TODO: log an error if file name is in the wrong format
-        let version = file_name_s[1].replace(".crumb", "");
+        let info = CrumbInfo::from_crumb(path)?;
+        let installed_size = Crumb::installed_size(path)?;
 
-        DatabaseEntry {
+        let build_date = meta.modified().ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs());
+
+        Ok(DatabaseEntry {
             name: name.to_string(),
-            version: version.to_string(),
+            version,
 
             size: meta.len(),
-            checksum: hash
+            checksum: hash,
+
+            // an empty requirement means any version, like *
+            dependencies: info.dependencies.unwrap_or_default().into_iter()
+                .map(|(name, requirement)| if requirement.trim().is_empty() { (name, "*".to_string()) } else { (name, requirement) })
+                .collect(),
+
+            description: info.package.description,
+            license: info.package.license,
+            architecture: None,
+            installed_size: Some(installed_size),
+            build_date
+        })
+    }
+
+    // entries without an architecture or with "any" run everywhere
+    pub fn is_for_architecture<S: AsRef<str>>(&self, architecture: S) -> bool {
+        match &self.architecture {
+            Some(entry_architecture) => entry_architecture == "any" || entry_architecture == architecture.as_ref(),
+            None => true,
         }
     }
 
-    fn new() -> DatabaseEntry {
-        DatabaseEntry {
-            name: String::default(),
-            version: String::default(),
-            checksum: String::default(),
-            size: 0
+    // the file name of the crumb on a mirror and in the cache
+    pub fn file_name(&self) -> String {
+        format!("{}@{}.crumb", self.name, self.version)
+    }
+
+    // checks a file against the size and checksum of this entry
+    pub fn verify<P: AsRef<Path>>(&self, path: P) -> Result<bool> {
+        let path = path.as_ref();
+        let mut file = fs::File::open(path).at(path)?;
+
+        if file.metadata().at(path)?.len() != self.size {
+            return Ok(false);
+        }
+
+        let mut sha512 = Sha512::default();
+        io::copy(&mut file, &mut sha512).at(path)?;
+
+        Ok(hex::encode(sha512.result()) == self.checksum)
+    }
+
+    pub fn new() -> DatabaseEntry {
+        DatabaseEntry::default()
+    }
+
+    // reads an indented VERSION_2_0 field that belongs to this entry
+    fn parse_field(&mut self, row: &str) {
+        let row = row.trim();
+        let (key, value) = match row.find(char::is_whitespace) {
+            Some(i) => (&row[..i], row[i..].trim()),
+            None => (row, ""),
+        };
+
+        match key {
+            "description" => self.description = Some(unescape(value)),
+            "license" => self.license = Some(unescape(value)),
+            "architecture" => self.architecture = Some(unescape(value)),
+            "installed_size" => self.installed_size = value.parse().ok(),
+            "build_date" => self.build_date = value.parse().ok(),
+
+            "depends" => {
+                let mut dependency = value.splitn(2, char::is_whitespace);
+                let name = dependency.next().unwrap_or_default();
+                let requirement = dependency.next().map_or("*".to_string(), |requirement| unescape(requirement.trim()));
+
+                self.dependencies.insert(name.to_string(), requirement);
+            }
+
+            _ => trace!("Ignoring unknown field {} of {}", key, pkg_name(&self.name)),
+        }
+    }
+
+    // what from_string can't read back the same way is refused
+    fn check_writable(&self) -> Result<()> {
+        let error = |reason: String| BreadError::parse(format!("{}@{}", self.name, self.version), reason);
+        let plain = |name: &str| !name.is_empty() && !name.starts_with('#') && !name.contains(char::is_whitespace);
+
+        if !plain(&self.name) {
+            return Err(error("a name in a database can't be empty, start with a # or contain whitespace".to_string()));
+        }
+
+        let version = self.version.to_string();
+        if version.contains(|c: char| c == '@' || c.is_whitespace()) {
+            return Err(error(format!("the version {} can't contain an @ or whitespace", version)));
+        }
+
+        for (name, requirement) in &self.dependencies {
+            if !plain(name) || requirement.is_empty() {
+                return Err(error(format!("the dependency {} \"{}\" needs a plain name and a requirement, * for any version", name, requirement)));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_fields(&self, out: &mut String) {
+        // a field is a single line
+        let mut field = |key: &str, value: &str| {
+            out.push_str(format!("    {} {}\n", key, escape(value)).as_str());
+        };
+
+        if let Some(description) = &self.description {
+            field("description", description);
+        }
+
+        if let Some(license) = &self.license {
+            field("license", license);
+        }
+
+        if let Some(architecture) = &self.architecture {
+            field("architecture", architecture);
+        }
+
+        if let Some(installed_size) = self.installed_size {
+            field("installed_size", &installed_size.to_string());
+        }
+
+        if let Some(build_date) = self.build_date {
+            field("build_date", &build_date.to_string());
+        }
+
+        for (name, requirement) in &self.dependencies {
+            out.push_str(format!("    depends {} {}\n", name, escape(requirement)).as_str());
         }
     }
 }
 
 impl Database {
-    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) {
+    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
         let path = path.as_ref().join(self.name.to_owned() + ".db.gz");
-        let file = fs::File::create(&path).unwrap();
+        let file = fs::File::create(&path).at(&path)?;
         
+        trace!("Saving to {}", pkg_name(path.to_string_lossy()));
+
+        let mut enc = GzEncoder::new(file, Compression::new(9));
+        enc.write_all(self.serialize()?.as_bytes()).at(&path)?;
+        enc.finish().at(&path)?;
+
+        Ok(())
+    }
+
+    // the counterpart of from_string, Database::from_string(name, db.serialize()?) == db
+    pub fn serialize(&self) -> Result<String> {
         let mut end_db_str = String::default();
-        end_db_str.push_str("VERSION_1_0\n");
+        end_db_str.push_str("VERSION_2_0\n");
+
         for entry in &self.entries {
-            end_db_str.push_str(format!("{}-{} {} {}\n", entry.name, entry.version, entry.size, entry.checksum).as_str());
+            entry.check_writable()?;
+            end_db_str.push_str(format!("{}@{} {} {}\n", entry.name, entry.version, entry.size, entry.checksum).as_str());
+            entry.write_fields(&mut end_db_str);
         }
 
-        trace!("Saving to {}", pkg_name(path.to_str().unwrap()));
-
-        let mut enc = GzEncoder::new(file, Compression::new(9));
-        enc.write_all(end_db_str.as_bytes()).unwrap(); // TODO: check for error
-        enc.finish().unwrap(); // TODO: check for error
+        Ok(end_db_str)
     }
 
-    pub async fn from_mirror<S: AsRef<str>, SI: AsRef<str>>(uri: S, name: SI) -> Database {
+    // without a keyring the signature of the database isn't checked
+    pub async fn from_mirror<S: AsRef<str>, SI: AsRef<str>>(downloader: &Downloader, uri: S, name: SI, keyring: Option<&Keyring>) -> Result<Database> {
         let name = name.as_ref();
         let uri = uri.as_ref();
 
@@ -104,38 +328,97 @@ impl Database {
         database_url.push_str(name);
         database_url.push_str(".db.gz");
 
-        let gz = crate::net::download_file(database_url).await.unwrap(); // TODO: check for error
-        // TODO: verify SHA512
+        let gz = downloader.download_file(&database_url, None).await?;
+
+        if let Some(keyring) = keyring {
+            let gz_path = Path::new(PATH_CACHE).join(name.to_string() + ".db.gz");
+
+            downloader.download_file(database_url + ".sig", None).await?;
+
+            match keyring.verify_file(&gz_path, signature_path(&gz_path))? {
+                Some(key) => trace!("{} is signed by {}", pkg_name(name), pkg_name(key)),
+                None => return Err(BreadError::Signature { name: name.to_string() }),
+            }
+        }
 
         info!("Extracting {}", pkg_name(name));
 
         let mut file = GzDecoder::new(gz);
         let mut buf = String::default();
-        file.read_to_string(&mut buf).unwrap(); // TODO: check for errors
+        file.read_to_string(&mut buf).map_err(|err| BreadError::parse(name.to_string() + ".db.gz", err))?;
 
         info!("Done, took {}ms", now.elapsed().as_millis());
 
         Database::from_string(name, buf)
     }
 
-    pub fn from_string<S: AsRef<str>, SI: AsRef<str>>(name: S, data: SI) -> Database {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Database> {
+        let path = path.as_ref();
+        let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+        let name = file_name.trim_end_matches(".db.gz");
+
+        let mut file = GzDecoder::new(fs::File::open(path).at(path)?);
+        let mut buf = String::default();
+        file.read_to_string(&mut buf).at(path)?;
+
+        Database::from_string(name, buf)
+    }
+
+    // Loads every {db_name}.db.gz inside of path
+    pub fn load_all<P: AsRef<Path>>(path: P) -> Result<Vec<Database>> {
+        let path = path.as_ref();
+        let mut databases = vec![];
+
+        for entry in path.read_dir().at(path)? {
+            let entry = entry.at(path)?;
+            let file_name = entry.file_name();
+
+            if !file_name.to_str().unwrap_or_default().ends_with(".db.gz") {
+                continue;
+            }
+
+            trace!("Loading {}", pkg_name(file_name.to_string_lossy()));
+            databases.push(Database::from_file(entry.path())?);
+        }
+
+        Ok(databases)
+    }
+
+    pub fn from_string<S: AsRef<str>, SI: AsRef<str>>(name: S, data: SI) -> Result<Database> {
         let name = name.as_ref();
         let data = data.as_ref();
 
-        let database_rows = data.lines();
-
         let mut db_major = 0;
-        let mut db_minor = 0;
 
-        let mut database_entries = vec![];
+        let mut database_entries: Vec<DatabaseEntry> = vec![];
+
+        for (i, row) in data.lines().enumerate() {
+            let error = |reason: &str| BreadError::parse(format!("{} line {}", name, i + 1), reason);
+
+            if row.trim().is_empty() || row.trim_start().starts_with('#') {
+                continue;
+            }
 
-        for row in database_rows {
-            if row.starts_with("VERSION_") {
-                let v = row.replace("VERSION_", "");
-                let v_split: Vec<&str> = v.split("_").collect();
+            if let Some(version) = row.strip_prefix("VERSION_") {
+                let mut v = version.splitn(2, '_');
 
-                db_major = v_split[0].parse().unwrap(); // TODO: instead of crashing, show the error cause.
-                db_minor = v_split[1].parse().unwrap();
+                db_major = v.next().and_then(|major| major.trim().parse::<u32>().ok())
+                    .ok_or_else(|| error("expected VERSION_$MAJOR_$MINOR"))?;
+                v.next().and_then(|minor| minor.trim().parse::<u32>().ok())
+                    .ok_or_else(|| error("expected VERSION_$MAJOR_$MINOR"))?;
+
+                continue;
+            }
+
+            if db_major == 0 {
+                return Err(error("expected the VERSION_ before the first entry"));
+            }
+
+            if db_major >= 2 && row.starts_with(char::is_whitespace) {
+                match database_entries.last_mut() {
+                    Some(entry) => entry.parse_field(row),
+                    None => return Err(error("field without an entry")),
+                }
 
                 continue;
             }
@@ -144,90 +427,196 @@ impl Database {
 
             let mut entry = DatabaseEntry::new();
 
-            if db_major >= 1 && db_minor >= 0 {
-                let name_version: Vec<&str> = row_split.next().unwrap().splitn(2, "@").collect();
+            let (name, version) = row_split.next().and_then(split_name_version)
+                .ok_or_else(|| error("expected $NAME@$VERSION"))?;
 
-                entry.name = name_version[0].to_string();
-                entry.size = row_split.next().unwrap().parse().unwrap(); // TODO: instead of crashing, show the error cause.
-                entry.checksum = row_split.next().unwrap().to_string();
-                entry.version = name_version[1].to_string();
-            }
+            entry.name = name.to_string();
+            entry.version = version.parse().map_err(|err: ParseVersionError| error(&err.to_string()))?;
+            entry.size = row_split.next().and_then(|size| size.parse().ok())
+                .ok_or_else(|| error("expected the size in bytes after the version"))?;
+            entry.checksum = row_split.next()
+                .ok_or_else(|| error("expected the sha512 after the size"))?
+                .to_string();
 
             database_entries.push(entry);
         }
 
-        Database {
+        Ok(Database {
             name: name.to_string(),
             entries: database_entries
-        }
+        })
     }
 
-    pub async fn from_folder<P: AsRef<Path>>(path: P, name: &str, architecture: &str) -> Database {
-        let path = path.as_ref();
-        let crumbs_path = path.join("crumbs");
-
-        let mut database_entries = vec![];
-
-        if !crumbs_path.exists() {
-            error!("{} directory doesn't exists! it's required for building a database.", pkg_name("crumbs"));
-            std::process::exit(1);
-        } else {
-            let architecture_path = crumbs_path.join(architecture); // TODO: check if exists
-            let crumbs = architecture_path.read_dir().unwrap(); // TODO: check for read permissions
+    // Builds a database out of every crumb in {path}/crumbs/{architecture}/
+    pub async fn from_folder<P: AsRef<Path>>(path: P, name: &str, architecture: &str) -> Result<Database> {
+        let architecture_path = path.as_ref().join("crumbs").join(architecture);
 
-            let mut i = 0; // there is no length
-            for _ in crumbs {
-                i += 1;
-            }
-
-            let mpb = MultiProgress::new();
+        let crumbs = architecture_path.read_dir().at(&architecture_path)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<io::Result<Vec<PathBuf>>>()
+            .at(&architecture_path)?;
 
-            let pb = mpb.add(ProgressBar::new(i));
-            pb.set_style(install_pg_style());
+        let mut database_entries = vec![];
 
-            for entry in architecture_path.read_dir().unwrap() { // TODO: check for read permissions
-                let entry = entry.unwrap();
+        let mpb = MultiProgress::new();
 
-                let entry = DatabaseEntry::from_file(entry.path()).await;
+        let pb = mpb.add(ProgressBar::new(crumbs.len() as u64));
+        pb.set_style(install_pg_style());
 
-                mpb.clear().unwrap();
-                mpb.set_draw_target(ProgressDrawTarget::hidden());
-                info!("Entry: {}@{}", pkg_name(entry.name.as_str()), entry.version);
-                mpb.set_draw_target(ProgressDrawTarget::stdout());
+        for crumb in crumbs {
+            let mut entry = DatabaseEntry::from_file(crumb).await?;
+            entry.architecture = Some(architecture.to_string());
 
-                pb.inc(1);
+            // the bars are only drawn again, failing to do so doesn't matter
+            let _ = mpb.clear();
+            mpb.set_draw_target(ProgressDrawTarget::hidden());
+            info!("Entry: {}@{}", pkg_name(entry.name.as_str()), entry.version);
+            mpb.set_draw_target(ProgressDrawTarget::stdout());
 
-                mpb.tick_and_clear(TickTimeLimit::Indefinite).unwrap();
+            pb.inc(1);
 
-                database_entries.push(entry);
-            }
+            let _ = mpb.tick_and_clear(TickTimeLimit::Indefinite);
 
-            mpb.clear().unwrap();
+            database_entries.push(entry);
         }
 
-        Database {
+        let _ = mpb.clear();
+
+        Ok(Database {
             name: name.to_string(),
             entries: database_entries
-        }
+        })
     }
 
     // Query through all databases in path
     // folder structure must look as followed:
     //
-    //  /x86_64/{db_name}.gz
-    //  /i686/{db_name}.gz
+    //  /x86_64/{db_name}.db.gz
+    //  /i686/{db_name}.db.gz
     //
-    pub fn query<P: AsRef<Path>, S: AsRef<str>, SI: AsRef<str>>(path: P, pkg_name: S, architecture: SI) {
-        let dir = path.as_ref().join(architecture.as_ref()).as_path();
+    pub fn query<P: AsRef<Path>, S: AsRef<str>, SI: AsRef<str>>(path: P, pkg_name: S, architecture: SI) -> Result<Option<DatabaseEntry>> {
+        let architecture = architecture.as_ref();
+        let databases = Database::load_all(path.as_ref().join(architecture))?;
+
+        Ok(databases.iter().find_map(|db| db.query_s(pkg_name.as_ref(), architecture).cloned()))
     }
 
     pub fn query_s<S: AsRef<str>, SI: AsRef<str>>(&self, pkg_name: S, architecture: SI) -> Option<&DatabaseEntry> {
-        for entry in &self.entries {
-            if entry.name == pkg_name.as_ref() {
-                return Some(entry);
-            }
-        }
-
-        None
+        // VERSION_1_0 databases don't know the architecture
+        self.entries.iter().find(|entry| entry.name == pkg_name.as_ref() && entry.is_for_architecture(&architecture))
     }
 }
+
+#[test]
+fn parse_database_v2() {
+    let data = "VERSION_2_0
+coreutils@8.32 11603163 e36f3d80
+    description The GNU Core Utilities
+    license GPLv3
+    architecture x86_64
+    installed_size 41234567
+    build_date 1592000000
+    depends libc ^1.0
+    depends acl >=2.2, <3.0
+    some_future_field whatever
+libc@1.2 1234 a1b2c3
+";
+
+    let db = Database::from_string("leopard", data).unwrap();
+    assert_eq!(2, db.entries.len());
+
+    let coreutils = &db.entries[0];
+    assert_eq!(Some("The GNU Core Utilities".to_string()), coreutils.description);
+    assert_eq!(Some("GPLv3".to_string()), coreutils.license);
+    assert_eq!(Some("x86_64".to_string()), coreutils.architecture);
+    assert_eq!(Some(41234567), coreutils.installed_size);
+    assert_eq!(Some(1592000000), coreutils.build_date);
+    assert_eq!(Some(&"^1.0".to_string()), coreutils.dependencies.get("libc"));
+    assert_eq!(Some(&">=2.2, <3.0".to_string()), coreutils.dependencies.get("acl"));
+
+    assert!(db.entries[1].dependencies.is_empty());
+    assert_eq!(None, db.entries[1].description);
+
+    assert!(db.query_s("coreutils", "x86_64").is_some());
+    assert!(db.query_s("coreutils", "i686").is_none());
+
+    let v1 = Database::from_string("leopard", "VERSION_1_0\ncoreutils@8.32 11603163 e36f3d80\n").unwrap();
+    assert_eq!(11603163, v1.entries[0].size);
+    assert!(v1.query_s("coreutils", "i686").is_some());
+}
+
+#[test]
+fn database_round_trip() {
+    let entry = |name: &str, version: &str| DatabaseEntry {
+        name: name.to_string(),
+        version: version.parse().unwrap(),
+        size: 1234,
+        checksum: "e36f3d80".to_string(),
+        ..DatabaseEntry::new()
+    };
+
+    let mut gtk = entry("gtk+@3", "3.24.20-2");
+    gtk.description = Some("GIMP # Toolkit\n\nwidgets for C:\\ and \\n, indented\t".to_string());
+    gtk.license = Some(" LGPL-2.1 ".to_string());
+    gtk.installed_size = Some(0);
+    gtk.dependencies.insert("glib-2.0".to_string(), ">=2.64, <3".to_string());
+
+    let db = Database {
+        name: "leopard".to_string(),
+        entries: vec![
+            entry("lib-foo-2", "1.0"),
+            entry("c++", "1:10.1~rc.1"),
+            gtk,
+            entry("linux", "5.7.2"),
+            entry("linux", "5.4.46"),
+        ]
+    };
+
+    assert_eq!(db, Database::from_string("leopard", db.serialize().unwrap()).unwrap());
+
+    // what would be read back differently isn't written
+    let mut unwritable = db.clone();
+    unwritable.entries[0].name = "#lib-foo-2".to_string();
+    assert!(unwritable.serialize().is_err());
+
+    let mut unwritable = db.clone();
+    unwritable.entries[0].dependencies.insert("libc".to_string(), String::default());
+    assert!(unwritable.serialize().is_err());
+
+    let path = std::env::temp_dir().join("bread-database-round-trip");
+    fs::create_dir_all(path.join("x86_64")).unwrap();
+    db.save_to_file(&path).unwrap();
+    assert_eq!(db, Database::from_file(path.join("leopard.db.gz")).unwrap());
+
+    db.save_to_file(path.join("x86_64")).unwrap();
+    assert_eq!(Some(db.entries[3].clone()), Database::query(&path, "linux", "x86_64").unwrap());
+    assert_eq!(None, Database::query(&path, "glibc", "x86_64").unwrap());
+    fs::remove_dir_all(&path).unwrap();
+
+    let with_comments = "
+# generated by bread kitchen
+VERSION_2_0
+
+linux@5.7.2 1234 e36f3d80
+    # the newest one
+    description Linux
+
+linux@5.4.46 1234 e36f3d80
+";
+
+    let db = Database::from_string("leopard", with_comments).unwrap();
+    assert_eq!(2, db.entries.len());
+    assert_eq!(Some("Linux".to_string()), db.entries[0].description);
+    assert_eq!(None, db.entries[1].description);
+}
+
+#[test]
+fn database_errors() {
+    let error = |data: &str| Database::from_string("leopard", data).unwrap_err().to_string();
+
+    assert_eq!("Failed to parse leopard line 2: expected the size in bytes after the version", error("VERSION_2_0\ncoreutils@8.32 lots e36f3d80\n"));
+    assert_eq!("Failed to parse leopard line 1: expected the VERSION_ before the first entry", error("coreutils@8.32 11603163 e36f3d80\n"));
+    assert_eq!("Failed to parse leopard line 2: expected $NAME@$VERSION", error("VERSION_1_0\ncoreutils 11603163 e36f3d80\n"));
+    assert_eq!("Failed to parse leopard line 2: field without an entry", error("VERSION_2_0\n    license GPLv3\n"));
+    assert!(Database::from_string("leopard", "VERSION_2\n").is_err());
+}
@@ -0,0 +1,147 @@
+// every public function of bread returns this error, main.rs turns it into
+// a message and an exit code. functions which can only fail in one way
+// (downloads, the resolver, version parsing) have their own error type
+// which converts into this one.
+
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+
+use crate::net::{DownloadError, DownloadErrorKind};
+use crate::resolver::ResolveError;
+use crate::version::ParseVersionError;
+
+#[derive(Debug)]
+pub enum BreadError {
+    Io(io::Error),
+
+    // like Io, but we know which file it was about
+    File { path: PathBuf, source: io::Error },
+
+    // a database, crumb.toml, config or anything else that isn't what we expect
+    Parse { what: String, reason: String },
+
+    // something in the configuration is missing or invalid
+    Config(String),
+
+    Download(DownloadError),
+
+    // doesn't verify with any key of the keyring
+    Signature { name: String },
+
+    Resolve(ResolveError),
+
+    Script { script: String, status: ExitStatus },
+
+    // `bread lint` found problems, they are printed already
+    Lint { count: usize },
+
+    // baking the same package twice gave two different crumbs
+    NotReproducible { name: String, differences: Vec<String> },
+
+    // an entry of a crumb which would end up outside of the root it's unpacked into
+    Unsafe { crumb: String, entry: String, reason: String },
+
+    NotInstalled { name: String },
+
+    // removing the package would break the ones which depend on it
+    Dependents { name: String, dependents: Vec<String> },
+}
+
+pub type Result<T> = std::result::Result<T, BreadError>;
+
+impl BreadError {
+    pub fn parse<S: AsRef<str>, R: ToString>(what: S, reason: R) -> BreadError {
+        BreadError::Parse { what: what.as_ref().to_string(), reason: reason.to_string() }
+    }
+
+    // the exit code of bread when this error ends it, loosely after sysexits.h
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            BreadError::Io(_) | BreadError::File { .. } => 74,
+            BreadError::Parse { .. } => 65,
+            BreadError::Config(_) => 78,
+
+            BreadError::Download(err) => match err.kind {
+                DownloadErrorKind::Size { .. } | DownloadErrorKind::Checksum { .. } => 76,
+                _ => 69,
+            },
+
+            BreadError::Signature { .. } => 77,
+            BreadError::Resolve(_) => 3,
+            BreadError::Script { .. } => 70,
+            BreadError::Lint { .. } => 65,
+            BreadError::NotReproducible { .. } => 65,
+            BreadError::Unsafe { .. } => 65,
+            BreadError::NotInstalled { .. } => 3,
+            BreadError::Dependents { .. } => 3,
+        }
+    }
+}
+
+impl fmt::Display for BreadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BreadError::Io(err) => write!(f, "{}", err),
+            BreadError::File { path, source } => write!(f, "{}: {}", path.display(), source),
+
+            BreadError::Parse { what, reason } => write!(f, "Failed to parse {}: {}", what, reason),
+            BreadError::Config(reason) => write!(f, "{}", reason),
+
+            BreadError::Download(err) => write!(f, "{}", err),
+
+            BreadError::Signature { name } =>
+                write!(f, "The signature of {} doesn't verify with any trusted key", name),
+
+            BreadError::Resolve(err) => write!(f, "Failed to resolve the dependencies\n{}", err),
+            BreadError::Script { script, status } => write!(f, "{} exited with {}", script, status),
+            BreadError::Lint { count } => write!(f, "Found {} problem(s)", count),
+            BreadError::NotReproducible { name, differences } =>
+                write!(f, "{} is not reproducible\n  {}", name, differences.join("\n  ")),
+            BreadError::Unsafe { crumb, entry, reason } =>
+                write!(f, "Refusing to unpack {} of {}, {}", entry, crumb, reason),
+
+            BreadError::NotInstalled { name } => write!(f, "{} is not installed", name),
+            BreadError::Dependents { name, dependents } =>
+                write!(f, "{} is required by {}, remove them too with --cascade", name, dependents.join(", ")),
+        }
+    }
+}
+
+impl std::error::Error for BreadError {}
+
+impl From<io::Error> for BreadError {
+    fn from(err: io::Error) -> BreadError {
+        BreadError::Io(err)
+    }
+}
+
+impl From<DownloadError> for BreadError {
+    fn from(err: DownloadError) -> BreadError {
+        BreadError::Download(err)
+    }
+}
+
+impl From<ResolveError> for BreadError {
+    fn from(err: ResolveError) -> BreadError {
+        BreadError::Resolve(err)
+    }
+}
+
+impl From<ParseVersionError> for BreadError {
+    fn from(err: ParseVersionError) -> BreadError {
+        BreadError::parse("version", err)
+    }
+}
+
+// attaches the path to an io::Error, `File::open(&path).at(&path)?`
+pub trait IoContext<T> {
+    fn at<P: AsRef<Path>>(self, path: P) -> Result<T>;
+}
+
+impl<T> IoContext<T> for io::Result<T> {
+    fn at<P: AsRef<Path>>(self, path: P) -> Result<T> {
+        self.map_err(|source| BreadError::File { path: path.as_ref().to_path_buf(), source })
+    }
+}
@@ -0,0 +1,307 @@
+// unpacks the entries of a crumb below a root, nothing may end up outside of it:
+//
+//  - paths with .. or / in front are refused
+//  - parent directories which are symlinks have to resolve inside of the root
+//  - symlinks may not point above the root, absolute targets are taken relative to it
+//  - hard links have to point at a file unpacked from the same crumb before
+//  - setuid/setgid files and devices are only unpacked if the manifest declares them
+//
+// violations stop the extraction with BreadError::Unsafe.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+
+use nix::sys::stat::{makedev, mknod, Mode, SFlag};
+use tar::{Entry, EntryType};
+
+use crate::archive::{normalize, CrumbArchive, EntryKind};
+use crate::manifest::FileType;
+use crate::error::{BreadError, IoContext, Result};
+
+pub struct Extractor<'a> {
+    crumb: &'a CrumbArchive,
+
+    // canonical, so it can be compared with resolved paths
+    root: PathBuf,
+
+    // everything unpacked so far, hard links may only point at those
+    unpacked: HashSet<PathBuf>,
+}
+
+impl<'a> Extractor<'a> {
+    pub fn new<P: AsRef<Path>>(crumb: &'a CrumbArchive, root: P) -> Result<Extractor<'a>> {
+        let root = root.as_ref();
+        fs::create_dir_all(root).at(root)?;
+
+        Ok(Extractor {
+            crumb,
+            root: fs::canonicalize(root).at(root)?,
+            unpacked: HashSet::new(),
+        })
+    }
+
+    fn refuse<S: ToString>(&self, name: &Path, reason: S) -> BreadError {
+        BreadError::Unsafe {
+            crumb: self.crumb.path.display().to_string(),
+            entry: name.display().to_string(),
+            reason: reason.to_string(),
+        }
+    }
+
+    // Unpacks entry to name below the root, returns where it ended up
+    pub fn unpack<R: Read, P: AsRef<Path>>(&mut self, entry: &mut Entry<R>, name: P) -> Result<PathBuf> {
+        let name = name.as_ref();
+        let relative = match normalize(name) {
+            Some(relative) if !relative.as_os_str().is_empty() => relative,
+            _ => return Err(self.refuse(name, "it points outside of the root")),
+        };
+
+        let target = self.root.join(&relative);
+        self.create_parents(&relative)?;
+
+        let entry_type = entry.header().entry_type();
+        let mode = entry.header().mode().at(&self.crumb.path)?;
+        let declared = self.crumb.manifest.as_ref().and_then(|manifest| manifest.get(relative.to_string_lossy()));
+
+        if mode & 0o6000 != 0 && declared.map_or(true, |declared| declared.mode & 0o6000 != mode & 0o6000) {
+            return Err(self.refuse(name, format!("it is setuid or setgid ({:o}) but the manifest doesn't declare it", mode)));
+        }
+
+        // a symlink to a directory inside of the root stays, like /lib -> usr/lib on merged /usr systems
+        if entry_type == EntryType::Directory && self.links_inside(&target) {
+            self.unpacked.insert(target.clone());
+            return Ok(target);
+        }
+
+        // whatever else is in the way gets replaced instead of written through, it could be a
+        // symlink or a hard link to a file outside of the package
+        if let Ok(metadata) = fs::symlink_metadata(&target) {
+            if !metadata.is_dir() || entry_type != EntryType::Directory {
+                if metadata.is_dir() {
+                    fs::remove_dir(&target).at(&target)?;
+                } else {
+                    fs::remove_file(&target).at(&target)?;
+                }
+            }
+        }
+
+        match entry_type {
+            EntryType::Directory | EntryType::Regular | EntryType::Continuous => {
+                entry.unpack(&target).at(&target)?;
+            }
+
+            EntryType::Symlink => {
+                let link = entry.link_name().at(&self.crumb.path)?.unwrap_or_default().into_owned();
+                let parent = target.parent().unwrap_or(&self.root);
+                let depth = fs::canonicalize(parent).at(parent)?
+                    .strip_prefix(&self.root).map(|parent| parent.components().count()).unwrap_or(0);
+
+                if link.as_os_str().is_empty() || escapes(depth, &link) {
+                    return Err(self.refuse(name, format!("it links to {} outside of the root", link.display())));
+                }
+
+                entry.unpack(&target).at(&target)?;
+            }
+
+            EntryType::Link => {
+                let link = entry.link_name().at(&self.crumb.path)?.unwrap_or_default().into_owned();
+                let source = match self.crumb.classify(&link) {
+                    EntryKind::Metadata(source) | EntryKind::Payload(source) => self.root.join(source),
+                    EntryKind::Skip | EntryKind::Unsafe(_) => return Err(self.refuse(name, format!("it hard links to {} outside of the crumb", link.display()))),
+                };
+
+                if !self.unpacked.contains(&source) {
+                    return Err(self.refuse(name, format!("it hard links to {}, which isn't part of the crumb", link.display())));
+                }
+
+                fs::hard_link(&source, &target).at(&target)?;
+            }
+
+            EntryType::Char | EntryType::Block | EntryType::Fifo => {
+                if declared.map(|declared| declared.file_type) != Some(FileType::Device) {
+                    return Err(self.refuse(name, "it is a device but the manifest doesn't declare it"));
+                }
+
+                let kind = match entry_type {
+                    EntryType::Char => SFlag::S_IFCHR,
+                    EntryType::Block => SFlag::S_IFBLK,
+                    _ => SFlag::S_IFIFO,
+                };
+
+                let major = entry.header().device_major().at(&self.crumb.path)?.unwrap_or(0);
+                let minor = entry.header().device_minor().at(&self.crumb.path)?.unwrap_or(0);
+
+                mknod(&target, kind, Mode::from_bits_truncate(mode), makedev(major.into(), minor.into()))
+                    .map_err(|err| self.refuse(name, format!("creating the device failed: {}", err)))?;
+            }
+
+            other => return Err(self.refuse(name, format!("bread doesn't unpack {:?} entries", other))),
+        }
+
+        self.unpacked.insert(target.clone());
+        Ok(target)
+    }
+
+    // whether path is a symlink to a directory inside of the root
+    fn links_inside(&self, path: &Path) -> bool {
+        let is_symlink = fs::symlink_metadata(path).map(|metadata| metadata.file_type().is_symlink()).unwrap_or(false);
+
+        is_symlink && fs::canonicalize(path).map(|resolved| resolved.starts_with(&self.root) && resolved.is_dir()).unwrap_or(false)
+    }
+
+    // creates the missing parents of relative, the existing ones may not lead outside of the root
+    fn create_parents(&self, relative: &Path) -> Result<()> {
+        let mut current = self.root.clone();
+
+        for component in relative.parent().map(Path::components).into_iter().flatten() {
+            current.push(component);
+
+            match fs::symlink_metadata(&current) {
+                Ok(metadata) if metadata.file_type().is_symlink() => {
+                    let resolved = fs::canonicalize(&current).at(&current)?;
+
+                    if !resolved.starts_with(&self.root) {
+                        return Err(self.refuse(relative, format!("its parent {} links outside of the root", current.display())));
+                    }
+                }
+
+                Ok(_) => {}
+                Err(_) => fs::create_dir(&current).at(&current)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// whether a symlink depth directories below the root climbs above it
+fn escapes(depth: usize, link: &Path) -> bool {
+    let mut depth = if link.is_absolute() { 0 } else { depth };
+
+    for component in link.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => depth = 0,
+            Component::CurDir => {}
+            Component::ParentDir if depth == 0 => return true,
+            Component::ParentDir => depth -= 1,
+            Component::Normal(_) => depth += 1,
+        }
+    }
+
+    false
+}
+
+#[test]
+fn extract_unsafe_entries() {
+    use tar::{Archive, Builder, Header};
+
+    use crate::archive::FORMAT_VERSION;
+    use crate::crumb::CrumbInfo;
+    use crate::manifest::{Manifest, ManifestEntry};
+
+    // Header::set_path refuses .. so the name is written by hand
+    fn header(name: &str, entry_type: EntryType, mode: u32, link: Option<&str>) -> Header {
+        let mut header = Header::new_gnu();
+        header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+        header.set_entry_type(entry_type);
+        header.set_mode(mode);
+        header.set_size(0);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_mtime(0);
+
+        if let Some(link) = link {
+            header.set_link_name(link).unwrap();
+        }
+
+        header.set_cksum();
+        header
+    }
+
+    fn extract(crumb: &CrumbArchive, root: &Path, headers: Vec<Header>) -> Result<()> {
+        let mut builder = Builder::new(vec![]);
+        for header in &headers {
+            builder.append(header, &[][..]).unwrap();
+        }
+
+        let data = builder.into_inner().unwrap();
+        let mut archive = Archive::new(&data[..]);
+        archive.set_preserve_permissions(true);
+
+        let mut extractor = Extractor::new(crumb, root)?;
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let entry_path = entry.path().unwrap().into_owned();
+            let name = entry_path.strip_prefix("payload").unwrap_or(&entry_path).to_path_buf();
+
+            extractor.unpack(&mut entry, name)?;
+        }
+
+        Ok(())
+    }
+
+    let root = std::env::temp_dir().join("bread-extract-unsafe-entries");
+    let _ = fs::remove_dir_all(&root);
+
+    let mut crumb = CrumbArchive {
+        path: PathBuf::from("evil@1.0.crumb"),
+        format: FORMAT_VERSION,
+        info: CrumbInfo::from_string("[package]\nname = \"evil\"\nversion = \"1.0\"\n[scripts]\n").unwrap(),
+        manifest: None,
+        metadata: vec![],
+    };
+
+    let refused = |result: Result<()>| match result {
+        Err(BreadError::Unsafe { reason, .. }) => reason,
+        _ => panic!("should have been refused"),
+    };
+
+    refused(extract(&crumb, &root, vec![header("payload/../../etc/passwd", EntryType::Regular, 0o644, None)]));
+    refused(extract(&crumb, &root, vec![header("payload/usr/lib", EntryType::Symlink, 0o777, Some("../../etc"))]));
+    refused(extract(&crumb, &root, vec![header("payload/shadow", EntryType::Link, 0o644, Some("payload/etc/shadow"))]));
+    refused(extract(&crumb, &root, vec![header("payload/null", EntryType::Char, 0o666, None)]));
+    assert!(refused(extract(&crumb, &root, vec![header("payload/su", EntryType::Regular, 0o4755, None)])).contains("setuid"));
+
+    // symlinks which stay inside of the root are fine, absolute ones too
+    extract(&crumb, &root, vec![
+        header("payload/usr/lib/libhello.so", EntryType::Regular, 0o755, None),
+        header("payload/usr/lib/libhi.so", EntryType::Link, 0o755, Some("payload/usr/lib/libhello.so")),
+        header("payload/lib", EntryType::Symlink, 0o777, Some("usr/lib")),
+        header("payload/usr/lib64", EntryType::Symlink, 0o777, Some("/usr/lib")),
+    ]).unwrap();
+    assert!(root.join("usr/lib/libhi.so").is_file());
+
+    // directories which are symlinks inside of the root stay symlinks
+    extract(&crumb, &root, vec![
+        header("payload/lib/", EntryType::Directory, 0o755, None),
+        header("payload/lib/libhey.so", EntryType::Regular, 0o755, None),
+    ]).unwrap();
+    assert!(fs::symlink_metadata(root.join("lib")).unwrap().file_type().is_symlink());
+    assert!(root.join("usr/lib/libhey.so").is_file());
+
+    // a parent which is a symlink out of the root
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink("/etc", root.join("etc")).unwrap();
+        refused(extract(&crumb, &root, vec![header("payload/etc/passwd", EntryType::Regular, 0o644, None)]));
+    }
+
+    crumb.manifest = Some(Manifest {
+        entries: vec![ManifestEntry {
+            path: "su".to_string(),
+            file_type: FileType::File,
+            mode: 0o4755,
+            uid: 0,
+            gid: 0,
+            size: 0,
+            sha512: None,
+            target: None,
+        }],
+    });
+
+    extract(&crumb, &root, vec![header("payload/su", EntryType::Regular, 0o4755, None)]).unwrap();
+
+    fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,107 @@
+// packages installed into a root, each one keeps the metadata of its crumb
+// (crumb.toml, manifest.toml and the scripts) in {root}/etc/bread/installed/{name}/
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::constants::PATH_CONFIGS;
+use crate::crumb::CrumbInfo;
+use crate::manifest::{Manifest, MANIFEST_NAME};
+use crate::error::{IoContext, Result};
+
+pub struct InstalledPackage {
+    // the directory with the metadata
+    pub path: PathBuf,
+
+    pub info: CrumbInfo,
+
+    // None for packages installed from crumbs baked before manifests existed
+    pub manifest: Option<Manifest>,
+}
+
+// where the packages installed into root keep their metadata, /etc/bread/installed for /
+pub fn installed_dir<P: AsRef<Path>>(root: P) -> PathBuf {
+    root.as_ref().join(PATH_CONFIGS.trim_start_matches('/')).join("installed")
+}
+
+impl InstalledPackage {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<InstalledPackage> {
+        let path = path.as_ref();
+        let manifest_path = path.join(MANIFEST_NAME);
+
+        Ok(InstalledPackage {
+            path: path.to_path_buf(),
+            info: CrumbInfo::from_file(path.join("crumb.toml"))?,
+            manifest: if manifest_path.is_file() { Some(Manifest::from_file(manifest_path)?) } else { None },
+        })
+    }
+
+    // Every package installed into root, sorted by name
+    pub fn load_all<P: AsRef<Path>>(root: P) -> Result<Vec<InstalledPackage>> {
+        let path = installed_dir(root);
+        if !path.is_dir() {
+            return Ok(vec![]);
+        }
+
+        let mut paths = vec![];
+        for entry in fs::read_dir(&path).at(&path)? {
+            paths.push(entry.at(&path)?.path());
+        }
+
+        paths.sort();
+        paths.into_iter().filter(|path| path.is_dir()).map(InstalledPackage::load).collect()
+    }
+
+    pub fn name(&self) -> &str {
+        &self.info.package.name
+    }
+
+    pub fn depends_on<S: AsRef<str>>(&self, name: S) -> bool {
+        self.info.dependencies.as_ref().map_or(false, |dependencies| dependencies.contains_key(name.as_ref()))
+    }
+}
+
+// Names of the installed packages which depend on any of names, directly or through others
+// if transitive is set. names themselves are never part of it
+pub fn dependents<S: AsRef<str>>(installed: &[InstalledPackage], names: &[S], transitive: bool) -> Vec<String> {
+    let mut found: Vec<String> = names.iter().map(|name| name.as_ref().to_string()).collect();
+    let requested = found.len();
+
+    let mut i = 0;
+    while i < found.len() {
+        for package in installed {
+            if package.depends_on(&found[i]) && !found.iter().any(|name| name == package.name()) {
+                found.push(package.name().to_string());
+            }
+        }
+
+        i += 1;
+        if !transitive && i == requested {
+            break;
+        }
+    }
+
+    found.split_off(requested)
+}
+
+#[test]
+fn installed_dependents() {
+    let root = std::env::temp_dir().join("bread-installed-dependents");
+    let _ = fs::remove_dir_all(&root);
+
+    for (name, dependencies) in &[("libc", ""), ("acl", "libc = \"^1.0\"\n"), ("coreutils", "acl = \"^2.2\"\n"), ("hello", "")] {
+        let path = installed_dir(&root).join(name);
+        fs::create_dir_all(&path).unwrap();
+        fs::write(path.join("crumb.toml"), format!("[package]\nname = \"{}\"\nversion = \"1.0\"\n[scripts]\n[dependencies]\n{}", name, dependencies)).unwrap();
+    }
+
+    let installed = InstalledPackage::load_all(&root).unwrap();
+    assert_eq!(vec!["acl", "coreutils", "hello", "libc"], installed.iter().map(InstalledPackage::name).collect::<Vec<&str>>());
+    assert!(installed[0].manifest.is_none());
+
+    assert_eq!(vec!["acl"], dependents(&installed, &["libc"], false));
+    assert_eq!(vec!["acl", "coreutils"], dependents(&installed, &["libc"], true));
+    assert!(dependents(&installed, &["hello", "coreutils"], true).is_empty());
+
+    fs::remove_dir_all(&root).unwrap();
+}
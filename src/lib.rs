@@ -0,0 +1,42 @@
+// bread as a library, the `bread` binary is a thin command line layer over it.
+//
+//  let databases = bread::Database::load_all("/etc/bread/databases")?;
+//  let plan = bread::resolve(&databases, &["coreutils"])?;
+//
+//  let info = bread::CrumbInfo::from_crumb("/var/cache/bread/coreutils@8.32.crumb")?;
+//
+// every error converts into bread::BreadError, the paths bread uses are in bread::constants.
+
+pub mod archive;
+pub mod config;
+pub mod constants;
+pub mod crumb;
+pub mod database;
+pub mod error;
+pub mod extract;
+pub mod installed;
+pub mod lint;
+pub mod manifest;
+pub mod mirror;
+pub mod net;
+pub mod resolver;
+pub mod signature;
+pub mod style;
+pub mod transaction;
+pub mod upgrade;
+pub mod version;
+
+mod utils;
+
+pub use crate::archive::CrumbArchive;
+pub use crate::config::Config;
+pub use crate::crumb::{Crumb, CrumbInfo};
+pub use crate::database::{Database, DatabaseEntry};
+pub use crate::error::{BreadError, Result};
+pub use crate::installed::InstalledPackage;
+pub use crate::manifest::Manifest;
+pub use crate::mirror::{MirrorConfig, MirrorPool};
+pub use crate::net::Downloader;
+pub use crate::resolver::resolve;
+pub use crate::signature::Keyring;
+pub use crate::version::Version;
@@ -0,0 +1,416 @@
+// checks a crumb.toml against a set of rules, used by `bread lint` and `bread bake`
+//
+//  rule            severity        checks
+//  parse           error           crumb.toml is valid toml and every key has the right type
+//  unknown-key     warning         keys crumb.toml doesn't know about, most likely typos
+//  missing-field   error/warning   name and version are required, description, license,
+//                                  homepage, authors, install and uninstall are expected
+//  name            error           the name can't be empty, start with a . or contain whitespace or a /
+//  version         error           the version parses
+//  license         error/warning   the license is a SPDX expression like "MIT OR Apache-2.0",
+//                                  a license id SPDX doesn't know is a warning
+//  homepage        error           every homepage is a http:// or https:// url
+//  dependency      error           every dependency is in one of the databases and its requirement parses
+//  script          error           a script starting with a path (./build.sh) points to an executable file
+//
+// a lint is an error if the crumb can't be baked or installed the way it's meant to.
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use hyper::Uri;
+use toml::Value;
+
+use crate::crumb::{CrumbInfo, key_line};
+use crate::database::Database;
+use crate::resolver::VersionReq;
+use crate::version::Version;
+use crate::error::{IoContext, Result};
+
+// the license ids SPDX knows which are used by packages the most, anything else is only a warning
+const SPDX_LICENSES: &[&str] = &[
+    "0BSD", "AFL-3.0", "AGPL-3.0-only", "AGPL-3.0-or-later", "Apache-1.1", "Apache-2.0",
+    "Artistic-1.0", "Artistic-2.0", "BSD-1-Clause", "BSD-2-Clause", "BSD-3-Clause", "BSD-4-Clause",
+    "BSL-1.0", "bzip2-1.0.6", "CC-BY-4.0", "CC-BY-SA-4.0", "CC0-1.0", "CDDL-1.0", "CDDL-1.1",
+    "curl", "EPL-1.0", "EPL-2.0", "EUPL-1.2", "FTL", "GFDL-1.3-only", "GFDL-1.3-or-later",
+    "GPL-1.0-only", "GPL-1.0-or-later", "GPL-2.0-only", "GPL-2.0-or-later", "GPL-3.0-only",
+    "GPL-3.0-or-later", "IJG", "ISC", "LGPL-2.0-only", "LGPL-2.0-or-later", "LGPL-2.1-only",
+    "LGPL-2.1-or-later", "LGPL-3.0-only", "LGPL-3.0-or-later", "Libpng", "libtiff", "MIT",
+    "MIT-0", "MPL-1.1", "MPL-2.0", "MS-PL", "NCSA", "OFL-1.1", "OpenSSL", "PHP-3.01", "PostgreSQL",
+    "PSF-2.0", "Python-2.0", "Ruby", "Sleepycat", "Unicode-DFS-2016", "Unlicense", "Vim",
+    "W3C", "WTFPL", "X11", "Zlib", "ZPL-2.1",
+];
+
+const SPDX_EXCEPTIONS: &[&str] = &[
+    "Autoconf-exception-3.0", "Bison-exception-2.2", "Classpath-exception-2.0", "GCC-exception-3.1",
+    "LLVM-exception", "Linux-syscall-note", "OpenSSL-exception", "Qt-LGPL-exception-1.1",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lint {
+    pub rule: &'static str,
+    pub severity: Severity,
+
+    // the key the lint is about, like package.license
+    pub key: Option<String>,
+    pub line: Option<usize>,
+
+    pub message: String,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+impl Lint {
+    // file:line: severity[rule]: message, like compilers print them
+    pub fn to_text<S: AsRef<str>>(&self, file: S) -> String {
+        match self.line {
+            Some(line) => format!("{}:{}: {}[{}]: {}", file.as_ref(), line, self.severity, self.rule, self.message),
+            None => format!("{}: {}[{}]: {}", file.as_ref(), self.severity, self.rule, self.message),
+        }
+    }
+
+    // one json object per lint, for CI and editors
+    pub fn to_json<S: AsRef<str>>(&self, file: S) -> String {
+        let optional = |value: Option<String>| value.unwrap_or_else(|| "null".to_string());
+
+        format!(
+            r#"{{"file":{},"line":{},"severity":"{}","rule":"{}","key":{},"message":{}}}"#,
+            json_string(file.as_ref()),
+            optional(self.line.map(|line| line.to_string())),
+            self.severity,
+            self.rule,
+            optional(self.key.as_deref().map(json_string)),
+            json_string(&self.message),
+        )
+    }
+}
+
+fn json_string(data: &str) -> String {
+    let mut escaped = String::from("\"");
+
+    for c in data.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped.push('"');
+    escaped
+}
+
+struct Linter<'a> {
+    data: &'a str,
+    lints: Vec<Lint>,
+}
+
+impl<'a> Linter<'a> {
+    fn push<S: AsRef<str>>(&mut self, rule: &'static str, severity: Severity, key: Option<&str>, message: S) {
+        let line = key.and_then(|key| {
+            let mut parts = key.splitn(2, '.');
+            let section = parts.next().unwrap_or_default();
+
+            match parts.next() {
+                Some(key) => key_line(self.data, Some(section), key).or_else(|| key_line(self.data, None, section)),
+                None => key_line(self.data, None, section),
+            }
+        });
+
+        self.lints.push(Lint {
+            rule,
+            severity,
+            key: key.map(|key| key.to_string()),
+            line,
+            message: message.as_ref().to_string(),
+        });
+    }
+
+    fn error<S: AsRef<str>>(&mut self, rule: &'static str, key: &str, message: S) {
+        self.push(rule, Severity::Error, Some(key), message);
+    }
+
+    fn warning<S: AsRef<str>>(&mut self, rule: &'static str, key: &str, message: S) {
+        self.push(rule, Severity::Warning, Some(key), message);
+    }
+}
+
+fn get<'v>(table: &'v Value, key: &str) -> Option<&'v Value> {
+    let mut parts = key.splitn(2, '.');
+
+    table.get(parts.next()?)?.get(parts.next()?)
+}
+
+// walks a SPDX license expression, returns the license ids or what's wrong with it
+fn spdx_ids(expression: &str) -> std::result::Result<Vec<String>, String> {
+    let expression = expression.replace('(', " ( ").replace(')', " ) ");
+    let mut tokens = expression.split_whitespace();
+
+    let mut ids = vec![];
+    let mut expect_license = true;
+    let mut depth = 0;
+
+    while let Some(token) = tokens.next() {
+        match token {
+            "(" if expect_license => depth += 1,
+            ")" if !expect_license && depth > 0 => depth -= 1,
+            "AND" | "OR" if !expect_license => expect_license = true,
+
+            "WITH" if !expect_license => match tokens.next() {
+                Some(exception) if SPDX_EXCEPTIONS.iter().any(|e| e.eq_ignore_ascii_case(exception)) => {}
+                Some(exception) => return Err(format!("unknown license exception {}", exception)),
+                None => return Err("expected an exception after WITH".to_string()),
+            },
+
+            id if expect_license && !["(", ")", "AND", "OR", "WITH"].contains(&id) => {
+                let id = id.trim_end_matches('+');
+                if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || "-.:".contains(c)) {
+                    return Err(format!("{} is not a license id", token));
+                }
+
+                ids.push(id.to_string());
+                expect_license = false;
+            }
+
+            _ => return Err(format!("unexpected {}", token)),
+        }
+    }
+
+    if expect_license {
+        return Err("expected a license id".to_string());
+    }
+
+    if depth > 0 {
+        return Err("missing )".to_string());
+    }
+
+    Ok(ids)
+}
+
+fn lint_license(linter: &mut Linter, license: &str) {
+    let ids = match spdx_ids(license) {
+        Ok(ids) => ids,
+        Err(reason) => return linter.error("license", "package.license", format!("{} is not a SPDX license expression, {}", license, reason)),
+    };
+
+    for id in ids {
+        let custom = id.starts_with("LicenseRef-") || id.starts_with("DocumentRef-");
+
+        if !custom && !SPDX_LICENSES.iter().any(|known| known.eq_ignore_ascii_case(&id)) {
+            linter.warning("license", "package.license", format!("{} is not a known SPDX license id", id));
+        }
+    }
+}
+
+fn lint_homepage(linter: &mut Linter, homepage: &str) {
+    let valid = homepage.parse::<Uri>().ok()
+        .filter(|uri| uri.host().is_some())
+        .map_or(false, |uri| uri.scheme_str() == Some("http") || uri.scheme_str() == Some("https"));
+
+    if !valid {
+        linter.error("homepage", "package.homepage", format!("{} is not a http:// or https:// url", homepage));
+    }
+}
+
+fn lint_script<P: AsRef<Path>>(linter: &mut Linter, key: &str, script: &str, package_path: P) {
+    // only the first word can be a file, `make install` is left alone
+    let program = script.split_whitespace().next().unwrap_or_default();
+    if !program.contains('/') {
+        return;
+    }
+
+    let path = package_path.as_ref().join(program);
+    let metadata = match fs::metadata(&path) {
+        Ok(metadata) => metadata,
+        Err(_) => return linter.error("script", key, format!("{} doesn't exist", program)),
+    };
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        if metadata.permissions().mode() & 0o111 == 0 {
+            linter.error("script", key, format!("{} is not executable, try `chmod +x {}`", program, program));
+        }
+    }
+
+    if !metadata.is_file() {
+        linter.error("script", key, format!("{} is not a file", program));
+    }
+}
+
+// Lints the crumb.toml in data, scripts are looked up in package_path.
+// without databases the dependencies aren't checked
+pub fn lint_string<S: AsRef<str>, P: AsRef<Path>>(data: S, package_path: P, databases: Option<&[Database]>) -> Vec<Lint> {
+    let mut linter = Linter { data: data.as_ref(), lints: vec![] };
+
+    let table: Value = match toml::from_str(linter.data) {
+        Ok(table) => table,
+        Err(err) => {
+            linter.push("parse", Severity::Error, None, err.to_string());
+            return linter.lints;
+        }
+    };
+
+    for (key, _) in CrumbInfo::unknown_keys(linter.data) {
+        linter.warning("unknown-key", &key, format!("{} is not a key of crumb.toml", key));
+    }
+
+    for key in &["package.name", "package.version"] {
+        if get(&table, key).is_none() {
+            linter.error("missing-field", key, format!("{} is required", key));
+        }
+    }
+
+    for key in &["package.description", "package.license", "package.homepage", "package.authors", "scripts.install", "scripts.uninstall"] {
+        if get(&table, key).is_none() {
+            linter.warning("missing-field", key, format!("{} is not set", key));
+        }
+    }
+
+    if let Some(Value::String(name)) = get(&table, "package.name") {
+        if name.is_empty() || name.starts_with('.') || name.contains(|c: char| c.is_whitespace() || c == '/') {
+            linter.error("name", "package.name", format!("\"{}\" can't be empty, start with a . or contain whitespace or a /", name));
+        }
+    }
+
+    if let Some(Value::String(version)) = get(&table, "package.version") {
+        if let Err(err) = version.parse::<Version>() {
+            linter.error("version", "package.version", err.to_string());
+        }
+    }
+
+    if let Some(Value::String(license)) = get(&table, "package.license") {
+        lint_license(&mut linter, license);
+    }
+
+    if let Some(Value::Array(homepages)) = get(&table, "package.homepage") {
+        for homepage in homepages.iter().filter_map(Value::as_str) {
+            lint_homepage(&mut linter, homepage);
+        }
+    }
+
+    if let Some(Value::Table(dependencies)) = table.get("dependencies") {
+        for (name, requirement) in dependencies {
+            let key = format!("dependencies.{}", name);
+
+            if requirement.as_str().and_then(VersionReq::parse).is_none() {
+                linter.error("dependency", &key, format!("{} is not a version requirement", requirement));
+            }
+
+            if let Some(databases) = databases {
+                if !databases.iter().any(|db| db.entries.iter().any(|entry| &entry.name == name)) {
+                    linter.error("dependency", &key, format!("{} is in none of the databases", name));
+                }
+            }
+        }
+    }
+
+    if let Some(Value::Table(scripts)) = table.get("scripts") {
+        for (name, script) in scripts {
+            if let Some(script) = script.as_str() {
+                lint_script(&mut linter, &format!("scripts.{}", name), script, package_path.as_ref());
+            }
+        }
+    }
+
+    // types the rules above don't look at, only reported if nothing else is wrong
+    if linter.lints.iter().all(|lint| lint.severity != Severity::Error) {
+        if let Err(err) = CrumbInfo::from_string(linter.data) {
+            linter.push("parse", Severity::Error, None, err.to_string());
+        }
+    }
+
+    linter.lints.sort_by_key(|lint| (lint.line.unwrap_or(0), std::cmp::Reverse(lint.severity)));
+    linter.lints
+}
+
+// Lints {path}/crumb.toml
+pub fn lint_package<P: AsRef<Path>>(path: P, databases: Option<&[Database]>) -> Result<Vec<Lint>> {
+    let path = path.as_ref();
+    let crumb_toml = path.join("crumb.toml");
+    let data = fs::read_to_string(&crumb_toml).at(&crumb_toml)?;
+
+    Ok(lint_string(data, path, databases))
+}
+
+#[test]
+fn lint_crumb_toml() {
+    let lints = |data: &str| lint_string(data, ".", Some(&[]))
+        .into_iter()
+        .map(|lint| (lint.rule, lint.severity, lint.line))
+        .collect::<Vec<(&str, Severity, Option<usize>)>>();
+
+    let complete = r#"
+        [package]
+        name        = "linux-fs"
+        description = "Linux Filesystem"
+        version     = "1.0"
+        license     = "GPL-2.0-only WITH Linux-syscall-note OR (MIT AND LicenseRef-leopard)"
+        homepage    = ["https://leopard.mempler.de"]
+        authors     = ["Robin A. P. <me@mempler.de>"]
+
+        [scripts]
+        install   = "make install"
+        uninstall = "make uninstall"
+    "#;
+
+    assert!(lints(complete).is_empty());
+
+    let broken = r#"
+        [package]
+        name     = "linux fs"
+        version  = "1.0-"
+        license  = "GPLv3 OR"
+        homepage = ["leopard.mempler.de"]
+
+        [scripts]
+        install = "./missing-install.sh"
+
+        [dependencies]
+        glibc = ">=2.31,"
+    "#;
+
+    assert_eq!(vec![
+        ("missing-field", Severity::Warning, Some(2)),
+        ("missing-field", Severity::Warning, Some(2)),
+        ("name", Severity::Error, Some(3)),
+        ("version", Severity::Error, Some(4)),
+        ("license", Severity::Error, Some(5)),
+        ("homepage", Severity::Error, Some(6)),
+        ("missing-field", Severity::Warning, Some(8)),
+        ("script", Severity::Error, Some(9)),
+        ("dependency", Severity::Error, Some(12)),
+        ("dependency", Severity::Error, Some(12)),
+    ], lints(broken));
+
+    for name in &[".", "..", ".hidden"] {
+        let hidden = format!("[package]\nname = \"{}\"\nversion = \"1.0\"\n", name);
+        assert!(lints(&hidden).iter().any(|lint| lint.0 == "name"), "{}", name);
+    }
+
+    assert_eq!(vec![("parse", Severity::Error, None)], lints("[package\n"));
+    assert_eq!(Err("missing )".to_string()), spdx_ids("(MIT OR Apache-2.0"));
+    assert_eq!(Ok(vec!["GPL-2.0".to_string(), "MIT".to_string()]), spdx_ids("GPL-2.0+ AND MIT"));
+
+    let lint = Lint { rule: "license", severity: Severity::Warning, key: Some("package.license".to_string()), line: Some(5), message: "\"GPLv3\" is not known".to_string() };
+    assert_eq!("crumb.toml:5: warning[license]: \"GPLv3\" is not known", lint.to_text("crumb.toml"));
+    assert_eq!(r#"{"file":"crumb.toml","line":5,"severity":"warning","rule":"license","key":"package.license","message":"\"GPLv3\" is not known"}"#, lint.to_json("crumb.toml"));
+}
@@ -1,13 +1,11 @@
-mod database;
-mod net;
-mod utils;
-mod style;
-mod constants;
-mod crumb;
-mod mirror;
-
-use clap::{App, Arg, SubCommand, AppSettings};
-use crate::constants::PATH_CONFIGS;
+use clap::{App, Arg, ArgMatches, SubCommand, AppSettings};
+use bread::constants::{PATH_CONFIGS, PATH_CACHE, PATH_INSTALL};
+use bread::mirror::{MirrorConfig, MirrorPool};
+use bread::config::Config;
+use bread::database::Database;
+use bread::error::{BreadError, IoContext, Result};
+use bread::lint::Severity;
+use bread::{crumb, lint, net, signature, style, upgrade};
 
 #[tokio::main]
 async fn main() {
@@ -54,38 +52,118 @@ async fn main() {
                 .subcommand(SubCommand::with_name("bake")
                     .setting(AppSettings::ColoredHelp)
                     .about("Bakes a custom mirror for custom package(s)"))
+
+                .subcommand(SubCommand::with_name("keygen")
+                    .setting(AppSettings::ColoredHelp)
+                    .about("Generates a key pair for signing, {name}.pub belongs into /etc/bread/keys/")
+                    .arg(Arg::with_name("name")
+                        .help("Name of the key")
+                        .required(true)))
+
+                .subcommand(SubCommand::with_name("sign")
+                    .setting(AppSettings::ColoredHelp)
+                    .about("Signs database(s) and crumb(s), creates a .sig next to each file")
+                    .arg(Arg::with_name("key")
+                        .short("k")
+                        .help("Secret key to sign with")
+                        .takes_value(true)
+                        .required(true))
+                    .arg(Arg::with_name("files")
+                        .help("File(s) to sign")
+                        .required(true)
+                        .multiple(true)))
             )
 
             .subcommand(SubCommand::with_name("strip")
                 .setting(AppSettings::ColoredHelp)
-                .setting(AppSettings::SubcommandRequiredElseHelp)
-                .about("Installs packages in a folder, useful for making a linux distribution\n(E.G: `bread strip linux linux-fs coreutils bread grub2 -o ./leopard`) for a basic distribution"))
+                .setting(AppSettings::ArgRequiredElseHelp)
+                .about("Installs packages in a folder, useful for making a linux distribution\n(E.G: `bread strip linux linux-fs coreutils bread grub2 -o ./leopard`) for a basic distribution")
+                .arg(Arg::with_name("packages")
+                    .help("Package(s) to install")
+                    .required(true)
+                    .multiple(true))
+                .arg(Arg::with_name("output")
+                    .short("o")
+                    .long("output")
+                    .help("Folder the packages are installed into")
+                    .takes_value(true)
+                    .required(true))
+                .arg(Arg::with_name("insecure")
+                    .long("insecure")
+                    .help("Installs crumbs even if their signature doesn't verify")))
 
             .subcommand(SubCommand::with_name("bake")
                 .setting(AppSettings::ColoredHelp)
-                .about("Bakes a package into a .crumb file")
+                .about("Bakes a package into a .crumb file, set SOURCE_DATE_EPOCH for reproducible crumbs")
                 .arg(Arg::with_name("directory")
                     .help("Input directory of the package")
                     .default_value(".")
                 )
+                .arg(Arg::with_name("verify-reproducible")
+                    .long("verify-reproducible")
+                    .help("Bakes the package twice and fails if the crumbs differ"))
             )
 
+            .subcommand(SubCommand::with_name("lint")
+                .setting(AppSettings::ColoredHelp)
+                .about("Checks the crumb.toml of a package, fails if there are errors")
+                .arg(Arg::with_name("directory")
+                    .help("Input directory of the package")
+                    .default_value("."))
+                .arg(Arg::with_name("format")
+                    .long("format")
+                    .help("Output format, json prints one object per line")
+                    .possible_value("text")
+                    .possible_value("json")
+                    .default_value("text"))
+                .arg(Arg::with_name("strict")
+                    .long("strict")
+                    .help("Fails on warnings too")))
+
             .subcommand(SubCommand::with_name("install")
                 .setting(AppSettings::ColoredHelp)
-                .setting(AppSettings::SubcommandRequiredElseHelp)
-                .about("Install a package from the mirrors declared in /etc/bread/mirror.toml and /etc/bread/mirrors.d/"))
+                .setting(AppSettings::ArgRequiredElseHelp)
+                .about("Install a package from the mirrors declared in /etc/bread/mirror.toml and /etc/bread/mirrors.d/")
+                .arg(Arg::with_name("packages")
+                    .help("Package(s) to install")
+                    .required(true)
+                    .multiple(true))
+                .arg(Arg::with_name("insecure")
+                    .long("insecure")
+                    .help("Installs crumbs even if their signature doesn't verify")))
+
+            .subcommand(SubCommand::with_name("remove")
+                .setting(AppSettings::ColoredHelp)
+                .setting(AppSettings::ArgRequiredElseHelp)
+                .about("Removes installed package(s), modified files in /etc are kept")
+                .arg(Arg::with_name("packages")
+                    .help("Package(s) to remove")
+                    .required(true)
+                    .multiple(true))
+                .arg(Arg::with_name("cascade")
+                    .long("cascade")
+                    .help("Removes the packages depending on them too")))
 
             .subcommand(SubCommand::with_name("update")
                 .setting(AppSettings::ColoredHelp)
-                .about("Updates the package cache database(s)"))
+                .about("Updates the package cache database(s)")
+                .arg(Arg::with_name("insecure")
+                    .long("insecure")
+                    .help("Saves databases even if their signature doesn't verify")))
 
             .subcommand(SubCommand::with_name("upgrade")
                 .setting(AppSettings::ColoredHelp)
-                .about("Upgrade all packages")
+                .about("Upgrade all packages, the ones in [frozen-crumbs] of /etc/bread/config.toml stay as they are")
                 .arg(Arg::with_name("force")
                     .short("f")
+                    .long("force")
                     .help("Force upgrading a package (overrides freeze status)")
-                    .value_delimiter(" ")))
+                    .takes_value(true)
+                    .multiple(true)
+                    .value_delimiter(" "))
+                .arg(Arg::with_name("insecure")
+                    .long("insecure")
+                    .help("Installs crumbs even if their signature doesn't verify")))
         ;
 
     let matches = app.get_matches();
@@ -98,24 +176,166 @@ async fn main() {
 
     pretty_env_logger::init_custom_env("BREAD_VERBOSITY");
 
+    if let Err(err) = run(&matches).await {
+        log::error!("{}", err);
+        std::process::exit(err.exit_code());
+    }
+}
+
+async fn run(matches: &ArgMatches<'_>) -> Result<()> {
     match matches.subcommand_name() {
         Some(c) => {
             match c {
                 "update" => {
-                    // TODO: use multiple mirrors
-                    let db = database::Database::from_mirror("https://mirror.mempler.de", "leopard").await;
+                    let config = MirrorConfig::load(PATH_CONFIGS)?;
+
+                    if config.entries.is_empty() {
+                        log::warn!("No mirrors are declared in {}/mirror.toml or {}/mirrors.d/", PATH_CONFIGS, PATH_CONFIGS);
+                    }
+
+                    let databases_path = std::path::Path::new(PATH_CONFIGS).join("databases");
+                    std::fs::create_dir_all(&databases_path).at(&databases_path)?;
+                    std::fs::create_dir_all(PATH_CACHE).at(PATH_CACHE)?;
+
+                    let keyring = signature::system_keyring(matches.subcommand().1.unwrap().is_present("insecure"))?;
+                    let settings = Config::load(PATH_CONFIGS)?;
+                    let downloader = net::Downloader::new(&settings.network)?;
+
+                    let mut pool = MirrorPool::new(&config, settings.settings.region, std::path::Path::new(PATH_CACHE).join("mirrors.toml"), keyring, downloader);
+
+                    // the other databases are still fetched when one fails, the last error is returned
+                    let mut failed = None;
+                    for database in config.databases() {
+                        match pool.fetch(database).await {
+                            Ok(mirror) => {
+                                log::trace!("Fetched {} from {}", style::pkg_name(database), mirror.uri);
+                                mirror.db.save_to_file(&databases_path)?;
+                            }
+
+                            Err(err) => {
+                                log::error!("None of the mirrors of {} answered", style::pkg_name(database));
+                                failed = Some(err);
+                            }
+                        }
+                    }
+
+                    pool.save()?;
+
+                    if let Some(err) = failed {
+                        return Err(err);
+                    }
+                }
+
+                "kitchen" => {
+                    let kitchen_matches = matches.subcommand().1.unwrap();
+                    let output = kitchen_matches.value_of("output").unwrap();
+
+                    match kitchen_matches.subcommand() {
+                        ("keygen", Some(keygen_matches)) => {
+                            let name = keygen_matches.value_of("name").unwrap();
+
+                            signature::generate_keypair(output, name)?;
+
+                            log::info!("Created {}.key and {}.pub in {}", name, name, style::pkg_name(output));
+                        }
+
+                        ("sign", Some(sign_matches)) => {
+                            let key = sign_matches.value_of("key").unwrap();
+
+                            for file in sign_matches.values_of("files").unwrap() {
+                                let sig = signature::sign_file(file, key)?;
+                                log::info!("Signed {}", style::pkg_name(sig.to_string_lossy()));
+                            }
+                        }
 
-                    db.save_to_file(PATH_CONFIGS.to_string() + "/databases");
+                        _ => println!("{}", kitchen_matches.usage())
+                    }
                 }
 
                 "bake" => {
-                    let raw_path = matches.subcommand().1.unwrap().value_of("directory").unwrap();
+                    let bake_matches = matches.subcommand().1.unwrap();
+                    let raw_path = bake_matches.value_of("directory").unwrap();
+
+                    if bake_matches.is_present("verify-reproducible") {
+                        crumb::Crumb::verify_reproducible(raw_path)?;
+                    } else {
+                        crumb::Crumb::bake_package(raw_path)?;
+                    }
+                }
+
+                "lint" => {
+                    let lint_matches = matches.subcommand().1.unwrap();
+                    let path = std::path::Path::new(lint_matches.value_of("directory").unwrap());
+
+                    let databases_path = std::path::Path::new(PATH_CONFIGS).join("databases");
+                    let databases = if databases_path.is_dir() {
+                        Some(Database::load_all(&databases_path)?)
+                    } else {
+                        log::warn!("There are no databases in {}, dependencies are not checked", databases_path.display());
+                        None
+                    };
+
+                    let lints = lint::lint_package(path, databases.as_deref())?;
+                    let file = path.join("crumb.toml");
+
+                    for lint in &lints {
+                        match lint_matches.value_of("format") {
+                            Some("json") => println!("{}", lint.to_json(file.to_string_lossy())),
+                            _ => println!("{}", lint.to_text(file.to_string_lossy())),
+                        }
+                    }
+
+                    let count = lints.iter()
+                        .filter(|lint| lint.severity == Severity::Error || lint_matches.is_present("strict"))
+                        .count();
 
-                    crumb::Crumb::bake_package(raw_path);
+                    if count > 0 {
+                        return Err(BreadError::Lint { count });
+                    }
+                }
+
+                "install" => {
+                    let install_matches = matches.subcommand().1.unwrap();
+                    let packages: Vec<&str> = install_matches.values_of("packages").unwrap().collect();
+
+                    let crumbs = crumb::Crumb::cache_package(&packages, PATH_INSTALL, install_matches.is_present("insecure")).await?;
+
+                    for crumb in crumbs {
+                        crumb::Crumb::install_package(&crumb).await?;
+                    }
+                }
+
+                "upgrade" => {
+                    let upgrade_matches = matches.subcommand().1.unwrap();
+                    let forced: Vec<&str> = upgrade_matches.values_of("force").map(|values| values.collect()).unwrap_or_default();
+
+                    let upgrades = upgrade::plan_installed(PATH_INSTALL, &forced)?;
+                    if upgrades.is_empty() {
+                        log::info!("Everything is up to date");
+                        return Ok(());
+                    }
+
+                    println!("{}", upgrade::summary(&upgrades));
+                    upgrade::apply(&upgrades, PATH_INSTALL, upgrade_matches.is_present("insecure")).await?;
+                }
+
+                "remove" => {
+                    let remove_matches = matches.subcommand().1.unwrap();
+                    let packages: Vec<&str> = remove_matches.values_of("packages").unwrap().collect();
+
+                    crumb::Crumb::remove_packages(&packages, PATH_INSTALL, remove_matches.is_present("cascade"))?;
                 }
 
                 "strip" => {
+                    let strip_matches = matches.subcommand().1.unwrap();
+                    let packages: Vec<&str> = strip_matches.values_of("packages").unwrap().collect();
+                    let root = strip_matches.value_of("output").unwrap();
+
+                    let crumbs = crumb::Crumb::cache_package(&packages, root, strip_matches.is_present("insecure")).await?;
 
+                    for crumb in crumbs {
+                        crumb::Crumb::install_package_in(&crumb, root).await?;
+                    }
                 }
 
                 _ => println!("{}", matches.usage())
@@ -124,4 +344,6 @@ async fn main() {
 
         None => println!("{}", matches.usage())
     }
+
+    Ok(())
 }
@@ -0,0 +1,206 @@
+// every crumb carries a .CRUMB/manifest.toml with the files it installs,
+// install keeps it in /etc/bread/installed/{name}/ so they can be removed again
+//
+//  [[file]]
+//  path   = "usr/bin/hello"
+//  type   = "file"
+//  mode   = "755"
+//  uid    = 0
+//  gid    = 0
+//  size   = 1234
+//  sha512 = "e36f3d80..."
+//
+//  [[file]]
+//  path   = "usr/bin/hi"
+//  type   = "symlink"
+//  mode   = "777"
+//  uid    = 0
+//  gid    = 0
+//  size   = 0
+//  target = "hello"
+//
+// paths are relative to the root the crumb is installed into, parents come before their children.
+// the extractor only unpacks setuid/setgid files and devices which are declared here.
+
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Sha512, Digest};
+
+use crate::archive::CrumbArchive;
+use crate::error::{BreadError, IoContext, Result};
+
+pub const MANIFEST_NAME: &str = "manifest.toml";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileType {
+    File,
+    Directory,
+    Symlink,
+
+    // character and block devices and fifos, bake never archives them so only
+    // manifests written by hand declare them
+    Device,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub path: String,
+
+    #[serde(rename = "type")]
+    pub file_type: FileType,
+
+    // the permission bits, written in octal
+    #[serde(serialize_with = "serialize_mode", deserialize_with = "deserialize_mode")]
+    pub mode: u32,
+
+    pub uid: u64,
+    pub gid: u64,
+    pub size: u64,
+
+    // only files have a checksum, only symlinks a target
+    pub sha512: Option<String>,
+    pub target: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    #[serde(rename = "file", default)]
+    pub entries: Vec<ManifestEntry>,
+}
+
+fn serialize_mode<S: Serializer>(mode: &u32, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format!("{:o}", mode))
+}
+
+fn deserialize_mode<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<u32, D::Error> {
+    let mode = String::deserialize(deserializer)?;
+
+    u32::from_str_radix(&mode, 8).map_err(|_| serde::de::Error::custom(format!("{} is not an octal mode", mode)))
+}
+
+#[cfg(unix)]
+fn permissions(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+
+    metadata.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn permissions(metadata: &fs::Metadata) -> u32 {
+    if metadata.permissions().readonly() { 0o444 } else { 0o644 }
+}
+
+impl ManifestEntry {
+    // describes the file at path, which is installed to name. the owner is always root like in the crumb
+    pub fn from_file<N: AsRef<Path>, P: AsRef<Path>>(name: N, path: P) -> Result<Option<ManifestEntry>> {
+        let path = path.as_ref();
+        let metadata = fs::symlink_metadata(path).at(path)?;
+
+        let mut entry = ManifestEntry {
+            path: name.as_ref().to_string_lossy().to_string(),
+            file_type: FileType::File,
+            mode: permissions(&metadata),
+            uid: 0,
+            gid: 0,
+            size: 0,
+            sha512: None,
+            target: None,
+        };
+
+        if metadata.file_type().is_symlink() {
+            entry.file_type = FileType::Symlink;
+            entry.target = Some(fs::read_link(path).at(path)?.to_string_lossy().to_string());
+        } else if metadata.is_dir() {
+            entry.file_type = FileType::Directory;
+        } else if metadata.is_file() {
+            let mut sha512 = Sha512::default();
+            io::copy(&mut File::open(path).at(path)?, &mut sha512).at(path)?;
+
+            entry.size = metadata.len();
+            entry.sha512 = Some(hex::encode(sha512.result()));
+        } else {
+            return Ok(None);
+        }
+
+        Ok(Some(entry))
+    }
+}
+
+impl Manifest {
+    // files are (path inside of the crumb, path on disk), in the order they are archived
+    pub fn from_files(files: &[(PathBuf, PathBuf)]) -> Result<Manifest> {
+        let mut entries = vec![];
+
+        for (name, path) in files {
+            entries.extend(ManifestEntry::from_file(name, path)?);
+        }
+
+        Ok(Manifest { entries })
+    }
+
+    pub fn from_string<S: AsRef<str>>(data: S) -> Result<Manifest> {
+        toml::from_str(data.as_ref()).map_err(|err| BreadError::parse(MANIFEST_NAME, err))
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Manifest> {
+        let path = path.as_ref();
+        let data = fs::read_to_string(path).at(path)?;
+
+        toml::from_str(&data).map_err(|err| BreadError::parse(path.to_string_lossy(), err))
+    }
+
+    // Reads the manifest out of a baked .crumb, crumbs baked before manifests existed have none
+    pub fn from_crumb<P: AsRef<Path>>(path: P) -> Result<Option<Manifest>> {
+        Ok(CrumbArchive::open(path)?.manifest)
+    }
+
+    pub fn serialize(&self) -> String {
+        // a manifest only holds strings, numbers and arrays of tables, this can't fail
+        toml::to_string(self).unwrap_or_default()
+    }
+
+    pub fn get<S: AsRef<str>>(&self, path: S) -> Option<&ManifestEntry> {
+        self.entries.iter().find(|entry| entry.path == path.as_ref())
+    }
+}
+
+#[test]
+fn manifest_round_trip() {
+    let path = std::env::temp_dir().join("bread-manifest-round-trip");
+    let _ = fs::remove_dir_all(&path);
+    fs::create_dir_all(path.join("usr/bin")).unwrap();
+    fs::write(path.join("usr/bin/hello"), "#!/bin/sh\necho hello\n").unwrap();
+
+    let mut files = vec![
+        (PathBuf::from("usr"), path.join("usr")),
+        (PathBuf::from("usr/bin"), path.join("usr/bin")),
+        (PathBuf::from("usr/bin/hello"), path.join("usr/bin/hello")),
+    ];
+
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink("hello", path.join("usr/bin/hi")).unwrap();
+        files.push((PathBuf::from("usr/bin/hi"), path.join("usr/bin/hi")));
+    }
+
+    let manifest = Manifest::from_files(&files).unwrap();
+    assert_eq!(manifest, Manifest::from_string(manifest.serialize()).unwrap());
+
+    let hello = manifest.get("usr/bin/hello").unwrap();
+    assert_eq!(FileType::File, hello.file_type);
+    assert_eq!(21, hello.size);
+    assert_eq!(128, hello.sha512.as_ref().unwrap().len());
+    assert_eq!(FileType::Directory, manifest.get("usr/bin").unwrap().file_type);
+
+    #[cfg(unix)]
+    assert_eq!(Some("hello".to_string()), manifest.get("usr/bin/hi").unwrap().target);
+
+    assert!(manifest.serialize().contains("type = \"directory\""));
+    assert!(Manifest::from_string("[[file]]\npath = \"a\"\ntype = \"file\"\nmode = \"9\"\nuid = 0\ngid = 0\nsize = 0\n").is_err());
+
+    fs::remove_dir_all(&path).unwrap();
+}
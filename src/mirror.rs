@@ -1,7 +1,410 @@
-use hyper::Uri;
-use crate::database::Database;
+// mirrors are declared in /etc/bread/mirror.toml and /etc/bread/mirrors.d/*.toml
+//
+//  [[mirror]]
+//  de  = { url = "https://mirror.mempler.de", database = "leopard", branch = "daily" }
+//  all = { url = "https://mirror.mempler.de", database = "leopard", branch = "daily" }
+//
+//  [[mirror]]
+//  all = { url = "https://mirror.mempler.de", database = "core", branch = "daily" }
+//
+// the keys are regions, a file with a single [mirror] table works too.
+// the database is served at {url}/{branch}/{database}.db.gz,
+// the url can also be a local directory like file:///media/usb/bread or /srv/bread
+//
+// mirrors of the region set in config.toml are tried first, then "all", then the rest.
+// how fast a mirror answered and how often it failed in a row is kept in
+// /var/cache/bread/mirrors.toml, mirrors that failed last time are tried last
+// and the fastest one of a region is tried first.
+//
+// with a keyring every database and crumb has to carry a signature made by one of its keys.
+
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use std::collections::btree_map::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::database::{Database, DatabaseEntry};
+use crate::style::pkg_name;
+use crate::signature::{Keyring, signature_path};
+use crate::net::Downloader;
+use crate::error::{BreadError, IoContext, Result};
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct MirrorEntry {
+    #[serde(skip)]
+    pub region: String,
+
+    pub url: String,
+    pub database: String,
+    pub branch: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum MirrorTables {
+    One(BTreeMap<String, MirrorEntry>),
+    Many(Vec<BTreeMap<String, MirrorEntry>>),
+}
+
+#[derive(Deserialize)]
+struct MirrorFile {
+    mirror: Option<MirrorTables>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MirrorConfig {
+    pub entries: Vec<MirrorEntry>,
+}
 
 pub struct Mirror {
-    pub uri: Uri,
+    pub uri: String,
     pub db: Database
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MirrorStats {
+    pub latency_ms: Option<u64>,
+    pub failures: u64,
+}
+
+pub struct MirrorPool {
+    pub entries: Vec<MirrorEntry>,
+    pub region: Option<String>,
+
+    // mirror uri -> stats
+    pub stats: BTreeMap<String, MirrorStats>,
+    stats_path: PathBuf,
+
+    // None skips the signature checks
+    pub keyring: Option<Keyring>,
+
+    pub downloader: Downloader,
+}
+
+impl MirrorEntry {
+    // the base uri of the database and its crumbs
+    pub fn uri(&self) -> String {
+        let url = self.url.trim_end_matches('/');
+
+        match &self.branch {
+            Some(branch) => format!("{}/{}", url, branch),
+            None => url.to_string(),
+        }
+    }
+
+    pub fn crumb_uri(&self, entry: &DatabaseEntry) -> String {
+        format!("{}/crumbs/{}/{}", self.uri(), std::env::consts::ARCH, entry.file_name())
+    }
+}
+
+impl MirrorConfig {
+    pub fn from_string<S: AsRef<str>>(data: S) -> Result<MirrorConfig> {
+        let file: MirrorFile = toml::from_str(data.as_ref()).map_err(|err| BreadError::parse("mirror.toml", err))?;
+
+        let tables = match file.mirror {
+            Some(MirrorTables::One(table)) => vec![table],
+            Some(MirrorTables::Many(tables)) => tables,
+            None => vec![],
+        };
+
+        let mut entries = vec![];
+        for table in tables {
+            for (region, mut entry) in table {
+                entry.region = region;
+                entries.push(entry);
+            }
+        }
+
+        Ok(MirrorConfig { entries })
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<MirrorConfig> {
+        let path = path.as_ref();
+
+        let mut data = String::default();
+        File::open(path).at(path)?.read_to_string(&mut data).at(path)?;
+
+        MirrorConfig::from_string(data).map_err(|err| match err {
+            BreadError::Parse { reason, .. } => BreadError::parse(path.to_string_lossy(), reason),
+            err => err,
+        })
+    }
+
+    // Reads mirror.toml and every drop-in of mirrors.d inside of the config path,
+    // drop-ins are merged in alphabetical order after mirror.toml
+    pub fn load<P: AsRef<Path>>(config_path: P) -> Result<MirrorConfig> {
+        let config_path = config_path.as_ref();
+        let mut config = MirrorConfig::default();
+
+        let mirror_toml = config_path.join("mirror.toml");
+        if mirror_toml.exists() {
+            log::trace!("Reading {}", pkg_name(mirror_toml.to_string_lossy()));
+            config.merge(MirrorConfig::from_file(mirror_toml)?);
+        }
+
+        let mirrors_d = config_path.join("mirrors.d");
+        if mirrors_d.is_dir() {
+            let mut drop_ins = vec![];
+            for entry in mirrors_d.read_dir().at(&mirrors_d)? {
+                let path = entry.at(&mirrors_d)?.path();
+
+                if path.extension().map_or(false, |e| e == "toml") {
+                    drop_ins.push(path);
+                }
+            }
+
+            drop_ins.sort();
+
+            for drop_in in drop_ins {
+                log::trace!("Reading {}", pkg_name(drop_in.to_string_lossy()));
+                config.merge(MirrorConfig::from_file(drop_in)?);
+            }
+        }
+
+        Ok(config)
+    }
+
+    pub fn merge(&mut self, other: MirrorConfig) {
+        self.entries.extend(other.entries);
+    }
+
+    // every configured database, in the order they were declared
+    pub fn databases(&self) -> Vec<&str> {
+        let mut databases: Vec<&str> = vec![];
+
+        for entry in &self.entries {
+            if !databases.contains(&entry.database.as_str()) {
+                databases.push(&entry.database);
+            }
+        }
+
+        databases
+    }
+
+    pub fn mirror_for<S: AsRef<str>>(&self, database: S) -> Option<&MirrorEntry> {
+        self.entries.iter().find(|e| e.database == database.as_ref())
+    }
+}
+
+impl Mirror {
+    pub async fn fetch(downloader: &Downloader, entry: &MirrorEntry, keyring: Option<&Keyring>) -> Result<Mirror> {
+        let uri = entry.uri();
+        let db = Database::from_mirror(downloader, &uri, &entry.database, keyring).await?;
+
+        Ok(Mirror { uri, db })
+    }
+}
+
+impl MirrorPool {
+    // Reads the stats of the previous runs from stats_path, missing or broken stats are ignored
+    pub fn new<P: AsRef<Path>>(config: &MirrorConfig, region: Option<String>, stats_path: P, keyring: Option<Keyring>, downloader: Downloader) -> MirrorPool {
+        let stats_path = stats_path.as_ref().to_path_buf();
+
+        let stats = fs::read_to_string(&stats_path).ok()
+            .and_then(|data| toml::from_str(&data).ok())
+            .unwrap_or_default();
+
+        MirrorPool {
+            entries: config.entries.clone(),
+            region,
+            stats,
+            stats_path,
+            keyring,
+            downloader,
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let data = toml::to_string(&self.stats).map_err(|err| BreadError::parse("the mirror stats", err))?;
+
+        fs::write(&self.stats_path, data).at(&self.stats_path)
+    }
+
+    // the mirrors of a database, the one to try first comes first
+    pub fn ranked<S: AsRef<str>>(&self, database: S) -> Vec<&MirrorEntry> {
+        let region_rank = |entry: &MirrorEntry| {
+            if Some(&entry.region) == self.region.as_ref() { 0 }
+            else if entry.region == "all" { 1 }
+            else { 2 }
+        };
+
+        let mut mirrors: Vec<&MirrorEntry> = self.entries.iter()
+            .filter(|e| e.database == database.as_ref())
+            .collect();
+
+        // the region works like a priority, the latency only decides between mirrors of the same rank.
+        // a mirror without a measured latency is tried before slower ones so it gets measured,
+        // sort_by_key is stable, so mirrors that rank the same stay in declaration order.
+        mirrors.sort_by_key(|entry| {
+            let stats = self.stats.get(&entry.uri()).cloned().unwrap_or_default();
+
+            (stats.failures > 0, region_rank(entry), stats.latency_ms.unwrap_or(0))
+        });
+
+        mirrors
+    }
+
+    pub fn record_success(&mut self, entry: &MirrorEntry, latency_ms: Option<u64>) {
+        let stats = self.stats.entry(entry.uri()).or_default();
+
+        stats.failures = 0;
+        if latency_ms.is_some() {
+            stats.latency_ms = latency_ms;
+        }
+    }
+
+    pub fn record_failure(&mut self, entry: &MirrorEntry) {
+        self.stats.entry(entry.uri()).or_default().failures += 1;
+    }
+
+    // Fetches the database from the best mirror which answers, the error is the one of the last mirror
+    pub async fn fetch<S: AsRef<str>>(&mut self, database: S) -> Result<Mirror> {
+        let database = database.as_ref();
+        let mirrors: Vec<MirrorEntry> = self.ranked(database).into_iter().cloned().collect();
+
+        let mut last_error = BreadError::Config(format!("No mirror is declared for {}", database));
+        for entry in mirrors {
+            let now = Instant::now();
+
+            match Mirror::fetch(&self.downloader, &entry, self.keyring.as_ref()).await {
+                Ok(mirror) => {
+                    self.record_success(&entry, Some(now.elapsed().as_millis() as u64));
+                    return Ok(mirror);
+                }
+
+                Err(err) => {
+                    log::warn!("Mirror {} failed, trying the next one: {}", pkg_name(entry.uri()), err);
+                    self.record_failure(&entry);
+                    last_error = err;
+                }
+            }
+        }
+
+        Err(last_error)
+    }
+
+    // Downloads the .sig of a cached crumb from the mirror and checks it against the keyring
+    pub async fn verify_signature<P: AsRef<Path>>(&self, mirror: &MirrorEntry, entry: &DatabaseEntry, path: P) -> Result<()> {
+        let keyring = match &self.keyring {
+            Some(keyring) => keyring,
+            None => return Ok(()),
+        };
+
+        let path = path.as_ref();
+
+        self.downloader.download_file(mirror.crumb_uri(entry) + ".sig", None).await?;
+
+        match keyring.verify_file(path, signature_path(path))? {
+            Some(key) => {
+                log::trace!("{} is signed by {}", pkg_name(entry.file_name()), pkg_name(key));
+                Ok(())
+            }
+
+            None => {
+                log::warn!("The signature of {} from {} doesn't verify", pkg_name(entry.file_name()), pkg_name(mirror.uri()));
+                Err(BreadError::Signature { name: entry.file_name() })
+            }
+        }
+    }
+
+    // Downloads a crumb into the cache and verifies it, skipping the mirrors in tried.
+    // the error is the one of the last mirror
+    pub async fn download_crumb<S: AsRef<str>>(&mut self, database: S, entry: &DatabaseEntry, tried: &[MirrorEntry]) -> Result<PathBuf> {
+        let database = database.as_ref();
+        let mirrors: Vec<MirrorEntry> = self.ranked(database).into_iter().filter(|mirror| !tried.contains(mirror)).cloned().collect();
+
+        let mut last_error = BreadError::Config(format!("No other mirror is declared for {}", database));
+        for mirror in mirrors {
+            let uri = mirror.crumb_uri(entry);
+            let path = Path::new(crate::constants::PATH_CACHE).join(entry.file_name());
+
+            let verified = match self.downloader.download_file(&uri, Some(entry)).await {
+                Ok(_) => self.verify_signature(&mirror, entry, &path).await,
+                Err(err) => Err(err.into()),
+            };
+
+            match verified {
+                Ok(_) => {
+                    self.record_success(&mirror, None);
+                    return Ok(path);
+                }
+
+                Err(err) => {
+                    log::warn!("{}", err);
+                    self.record_failure(&mirror);
+                    last_error = err;
+                }
+            }
+        }
+
+        Err(last_error)
+    }
+}
+
+#[test]
+fn parse_mirror_config() {
+    let config = MirrorConfig::from_string(r#"
+        [[mirror]]
+        de  = { url = "https://mirror.mempler.de", database = "leopard", branch = "daily" }
+        all = { url = "https://mirror.mempler.de/", database = "leopard" }
+
+        [[mirror]]
+        all = { url = "https://mirror.mempler.de", database = "core", branch = "daily" }
+    "#).unwrap();
+
+    assert_eq!(3, config.entries.len());
+    assert_eq!(vec!["leopard", "core"], config.databases());
+    assert_eq!("all", config.mirror_for("leopard").unwrap().region);
+    assert_eq!("https://mirror.mempler.de", config.mirror_for("leopard").unwrap().uri());
+    assert_eq!("https://mirror.mempler.de/daily", config.mirror_for("core").unwrap().uri());
+
+    let single = MirrorConfig::from_string(r#"
+        [mirror]
+        all = { url = "file:///srv/mirror", database = "local" }
+    "#).unwrap();
+
+    assert_eq!(vec!["local"], single.databases());
+    assert!(MirrorConfig::from_string("").unwrap().entries.is_empty());
+}
+
+#[test]
+fn rank_mirrors() {
+    let config = MirrorConfig::from_string(r#"
+        [[mirror]]
+        de  = { url = "https://de.example.org", database = "leopard" }
+        us  = { url = "https://us.example.org", database = "leopard" }
+
+        [[mirror]]
+        all = { url = "https://all-1.example.org", database = "leopard" }
+
+        [[mirror]]
+        all = { url = "https://all-2.example.org", database = "leopard" }
+    "#).unwrap();
+
+    let stats_path = std::env::temp_dir().join("bread-rank-mirrors.toml");
+    let _ = fs::remove_file(&stats_path);
+
+    let mut pool = MirrorPool::new(&config, Some("de".to_string()), &stats_path, None, Downloader::new(&Default::default()).unwrap());
+    let ranked = |pool: &MirrorPool| pool.ranked("leopard").iter().map(|e| e.url.clone()).collect::<Vec<String>>();
+
+    let de = config.entries[0].clone();
+    let all_1 = config.entries[2].clone();
+    let all_2 = config.entries[3].clone();
+
+    assert_eq!(vec![&de.url, &all_1.url, &all_2.url, "https://us.example.org"], ranked(&pool));
+
+    pool.record_failure(&de);
+    pool.record_success(&all_1, Some(200));
+    pool.record_success(&all_2, Some(20));
+    assert_eq!(vec![&all_2.url, &all_1.url, "https://us.example.org", &de.url], ranked(&pool));
+
+    pool.save().unwrap();
+    let pool = MirrorPool::new(&config, Some("de".to_string()), &stats_path, None, Downloader::new(&Default::default()).unwrap());
+    assert_eq!(Some(20), pool.stats[&all_2.uri()].latency_ms);
+    assert_eq!(1, pool.stats[&de.uri()].failures);
+
+    fs::remove_file(&stats_path).unwrap();
+}
@@ -1,132 +1,726 @@
-use std::fs::File;
-use std::io::Write;
-use std::path::Path;
-
-use log::{info, trace};
-
-use hyper::{Client, Response, Body};
+// downloads are written to {file}.part and only moved into place once they are complete.
+// if we know the size and checksum of a file, an interrupted download is continued
+// with a Range request. it starts from zero if the server doesn't support it, or if the
+// .part doesn't fit the file on the server (a 416, or a Content-Range starting elsewhere).
+//
+// hyper doesn't follow redirects, so fetch does that itself. not every server sends
+// a Content-Length, then the size from the database is used or we just show a spinner.
+//
+// file:// uris and plain paths are read from the local filesystem, so a mirror can live
+// on a usb stick or be a directory made by `bread kitchen cook`. they go through the same
+// checks, a verified crumb on the same filesystem as the cache is hard-linked instead of copied.
+//
+// a Downloader shares one client between all downloads so connections are reused,
+// runs at most [network].parallel-downloads at once and tries a download again
+// after a connection error, a timeout or a 5xx, waiting 1s, 2s, 4s... in between.
+// proxies, certificates and timeouts are set up by the Connector, see connector.rs.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use log::{info, trace, warn};
+
+use futures::stream::{self, StreamExt};
+
+use hyper::{Client, Request, Response, Body, StatusCode, Uri};
+use hyper::header::{CONTENT_LENGTH, CONTENT_RANGE, LOCATION, PROXY_AUTHORIZATION, RANGE, USER_AGENT};
 use hyper::body::{HttpBody, Buf};
-use hyper_tls::HttpsConnector;
 
-use crate::style::{pkg_name, download_pg_style};
+use sha2::{Sha512, Digest};
+
+use crate::style::{pkg_name, download_pg_style, download_spinner_style};
 use crate::constants::PATH_CACHE;
+use crate::database::DatabaseEntry;
+use crate::config::Network;
+use crate::net::{Connector, DownloadError, DownloadErrorKind};
 use indicatif::{ProgressBar, MultiProgress, TickTimeLimit, ProgressDrawTarget};
 
-async fn save_to_file(file_name: &str, pb: &ProgressBar, res: &mut Response<Body>, mpb: Option<&MultiProgress>) -> File {
-    let file_path = Path::new(PATH_CACHE).join(file_name);
-    let mut file = File::create(&file_path).unwrap(); // TODO: print an error instead of crashing
+const MAX_REDIRECTS: usize = 10;
+const DEFAULT_PARALLEL_DOWNLOADS: usize = 4;
+const DEFAULT_RETRIES: u32 = 3;
+const DEFAULT_READ_TIMEOUT: u64 = 60;
+
+// keeps the average speed of everything downloaded under a limit,
+// time without downloads only builds up a second worth of bytes
+struct Throttle {
+    bytes_per_second: u64,
+    start: Instant,
+    received: u64,
+}
 
-    while let Some(next) = res.data().await {
-        let chunk = next.unwrap();
-        file.write(chunk.bytes()).unwrap();
-        pb.inc(chunk.len() as u64);
+#[derive(Clone)]
+pub struct Downloader {
+    client: Client<Connector>,
+    connector: Connector,
+
+    pub parallel_downloads: usize,
+    pub retries: u32,
+    pub read_timeout: Duration,
+    pub user_agent: String,
 
-        if mpb.is_some() {
-            mpb.unwrap().tick_and_clear(TickTimeLimit::Indefinite).unwrap();
+    throttle: Option<Arc<Mutex<Throttle>>>,
+}
+
+pub struct MultiDownloadResult {
+    pub file: File,
+    pub name: String
+}
+
+// resolves the Location of a redirect, relative locations stay on the same host
+fn redirect_target(base: &Uri, res: &Response<Body>) -> Result<Uri, DownloadErrorKind> {
+    let location = res.headers().get(LOCATION)
+        .and_then(|location| location.to_str().ok())
+        .ok_or_else(|| DownloadErrorKind::Redirect(format!("{} without a location", res.status())))?;
+
+    let invalid = || DownloadErrorKind::Redirect(format!("invalid location {}", location));
+
+    if location.contains("://") {
+        return location.parse::<Uri>().map_err(|_| invalid());
+    }
+
+    let path = if location.starts_with('/') {
+        location.to_string()
+    } else {
+        let base_path = base.path();
+        format!("{}{}", &base_path[..base_path.rfind('/').map_or(0, |i| i + 1)], location)
+    };
+
+    Uri::builder()
+        .scheme(base.scheme().ok_or_else(invalid)?.clone())
+        .authority(base.authority().ok_or_else(invalid)?.clone())
+        .path_and_query(path.as_str())
+        .build()
+        .map_err(|_| invalid())
+}
+
+impl Throttle {
+    // how long to wait after receiving bytes
+    fn delay(&mut self, bytes: u64) -> Duration {
+        let elapsed = self.start.elapsed();
+
+        let allowed = (elapsed.as_secs_f64() * self.bytes_per_second as f64) as u64;
+        if allowed > self.received + self.bytes_per_second {
+            self.received = allowed - self.bytes_per_second;
         }
+
+        self.received += bytes;
+
+        let due = Duration::from_secs_f64(self.received as f64 / self.bytes_per_second as f64);
+        due.checked_sub(elapsed).unwrap_or_default()
     }
+}
 
-    file.flush().unwrap();
+// fails with a timeout if nothing happened for too long
+async fn within<F: Future>(timeout: Duration, future: F) -> Result<F::Output, DownloadErrorKind> {
+    tokio::time::timeout(timeout, future).await
+        .map_err(|_| DownloadErrorKind::Timeout(timeout))
+}
 
-    if mpb.is_some() && log::max_level() == log::Level::Trace {
-        let mpb = mpb.unwrap();
-        mpb.clear().unwrap();
-        mpb.set_draw_target(ProgressDrawTarget::hidden());
-        trace!("Saving to {}", pkg_name(file_path.to_str().unwrap()));
-        mpb.set_draw_target(ProgressDrawTarget::stdout());
+async fn fetch(downloader: &Downloader, uri: Uri, offset: u64) -> Result<Response<Body>, DownloadErrorKind> {
+    let mut uri = uri;
+    for _ in 0..=MAX_REDIRECTS {
+        let mut req = Request::get(uri.clone())
+            .header(USER_AGENT, downloader.user_agent.as_str());
+
+        if offset > 0 {
+            req = req.header(RANGE, format!("bytes={}-", offset));
+        }
+
+        // plain http goes to the proxy as is, https is tunneled and authorizes itself
+        if uri.scheme_str() == Some("http") {
+            if let Some(authorization) = downloader.connector.proxy_for(&uri).and_then(|proxy| proxy.authorization.as_ref()) {
+                req = req.header(PROXY_AUTHORIZATION, authorization.as_str());
+            }
+        }
+
+        let req = req.body(Body::empty()).map_err(|err| DownloadErrorKind::Invalid(format!("can't request {}, {}", uri, err)))?;
+        let res = within(downloader.read_timeout, downloader.client.request(req)).await??;
+
+        if res.status().is_redirection() {
+            uri = redirect_target(&uri, &res)?;
+            trace!("Redirected to {}", pkg_name(uri.to_string()));
+            continue;
+        }
+
+        if !res.status().is_success() {
+            return Err(DownloadErrorKind::Status(res.status()));
+        }
+
+        return Ok(res);
+    }
+
+    Err(DownloadErrorKind::Redirect(format!("more than {} redirects", MAX_REDIRECTS)))
+}
+
+// where a file comes from
+enum Location {
+    Http(Uri),
+    Local(PathBuf),
+}
+
+// what is left to copy into the cache
+enum Source {
+    // the response and where it continues
+    Http(Response<Body>, u64),
+    Local(PathBuf),
+}
+
+fn locate(uri: &str) -> Result<Location, DownloadErrorKind> {
+    if let Some(path) = uri.strip_prefix("file://") {
+        return Ok(Location::Local(PathBuf::from(path)));
+    } else if uri.starts_with('/') || uri.starts_with('.') {
+        return Ok(Location::Local(PathBuf::from(uri)));
     }
 
-    File::open(file_path).unwrap()
+    match uri.parse::<Uri>() {
+        Ok(parsed) if parsed.scheme().is_some() && parsed.authority().is_some() => Ok(Location::Http(parsed)),
+        _ => Err(DownloadErrorKind::Invalid(format!("{} is not a valid uri, it needs a scheme like https://", uri))),
+    }
 }
 
-pub async fn download_file<S: AsRef<str>>(uri: S) -> Option<File> {
-    let uri = uri.as_ref();
+impl Location {
+    fn file_name(&self) -> Result<String, DownloadErrorKind> {
+        let path = match self {
+            Location::Http(uri) => Path::new(uri.path()),
+            Location::Local(path) => path.as_path(),
+        };
 
-    trace!("Downloading {}", pkg_name(uri));
+        path.file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .ok_or_else(|| DownloadErrorKind::Invalid(format!("{} doesn't name a file", path.display())))
+    }
+}
 
-    //let mut written = 0;
-    let url = uri.parse::<hyper::Uri>().unwrap();
+// the name a download gets in the cache
+fn file_name(uri: &str) -> Result<String, DownloadErrorKind> {
+    locate(uri)?.file_name()
+}
 
-    let tmp_path = url.path().to_string();
-    let url_path = Path::new(tmp_path.as_str());
-    let file_name = url_path.file_name().unwrap().to_str().unwrap();
+// a bar if the mirror or the database tells us how big the file is, a spinner otherwise
+fn progress_bar(file_name: &str, source: &Source, expected: Option<&DatabaseEntry>) -> ProgressBar {
+    let length = match source {
+        Source::Http(res, offset) => res.headers().get(CONTENT_LENGTH)
+            .and_then(|length| length.to_str().ok())
+            .and_then(|length| length.parse::<u64>().ok())
+            .map(|length| offset + length),
 
-    let https = HttpsConnector::new();
-    let client = Client::builder().build::<_, hyper::Body>(https);
-    let mut res = client.get(url).await.unwrap();
-    let expected_length = res.headers().get("Content-Length").unwrap().to_str().unwrap().parse().unwrap();
+        Source::Local(path) => fs::metadata(path).ok().map(|meta| meta.len()),
+    };
+
+    let pb = match length.or_else(|| expected.map(|expected| expected.size)) {
+        Some(length) => {
+            let pb = ProgressBar::new(length);
+            pb.set_style(download_pg_style());
+            pb
+        }
+        None => {
+            let pb = ProgressBar::new_spinner();
+            pb.set_style(download_spinner_style());
+            pb
+        }
+    };
 
-    let pb = ProgressBar::new(expected_length);
-    pb.set_style(download_pg_style());
     pb.set_prefix(&format!("[{}]", file_name));
+    pb
+}
+
+// hashes everything that passes through, if we know what to expect
+// we stop as soon as there is too much
+struct Verifier<'a> {
+    sha512: Sha512,
+    received: u64,
+    expected: Option<&'a DatabaseEntry>,
+}
+
+impl<'a> Verifier<'a> {
+    fn new(expected: Option<&'a DatabaseEntry>) -> Verifier<'a> {
+        Verifier { sha512: Sha512::default(), received: 0, expected }
+    }
+
+    fn update(&mut self, chunk: &[u8]) -> Result<(), DownloadErrorKind> {
+        self.received += chunk.len() as u64;
+
+        if let Some(expected) = self.expected {
+            if self.received > expected.size {
+                return Err(DownloadErrorKind::Size { expected: expected.size, received: self.received });
+            }
+        }
+
+        self.sha512.input(chunk);
+        Ok(())
+    }
 
-    let file = save_to_file(file_name, &pb, &mut res, None).await;
+    fn finish(self) -> Result<(), DownloadErrorKind> {
+        if let Some(expected) = self.expected {
+            if self.received != expected.size {
+                return Err(DownloadErrorKind::Size { expected: expected.size, received: self.received });
+            }
 
-    pb.finish_and_clear();
-    trace!("Finished downloading {}", pkg_name(file_name));
+            let actual = hex::encode(self.sha512.result());
+            if actual != expected.checksum {
+                return Err(DownloadErrorKind::Checksum { expected: expected.checksum.clone(), actual });
+            }
+        }
 
-    Some(file)
+        Ok(())
+    }
 }
 
-pub struct MultiDownloadResult {
-    pub file: File,
-    pub name: String
+fn part_path(file_path: &Path) -> PathBuf {
+    let mut path = file_path.as_os_str().to_owned();
+    path.push(".part");
+
+    PathBuf::from(path)
 }
 
-pub async fn download_files<S: AsRef<str>>(uris: Vec<S>) -> Vec<Option<MultiDownloadResult>> {
-    let mpb = MultiProgress::new();
+// how much of an interrupted download can be kept, without a checksum we can't trust old parts
+fn resume_offset(part_path: &Path, expected: Option<&DatabaseEntry>) -> u64 {
+    let expected = match expected {
+        Some(expected) => expected,
+        None => return 0,
+    };
+
+    match fs::metadata(part_path) {
+        Ok(meta) if meta.len() < expected.size => meta.len(),
+        Ok(_) => {
+            let _ = fs::remove_file(part_path);
+            0
+        }
+        Err(_) => 0,
+    }
+}
 
-    let mut futures = vec![];
-    for url in uris {
-        let future = async {
-            let url = url;
-            let url_ref = url.as_ref();
+// where a 206 continues, from Content-Range: bytes $START-$END/$SIZE
+fn content_range_start(res: &Response<Body>) -> Option<u64> {
+    let range = res.headers().get(CONTENT_RANGE)?.to_str().ok()?;
+    let (start, _) = range.trim().strip_prefix("bytes ")?.split_once('-')?;
 
-            mpb.clear().unwrap();
-            mpb.set_draw_target(ProgressDrawTarget::hidden());
-            trace!("Downloading {}", pkg_name(url_ref));
-            mpb.set_draw_target(ProgressDrawTarget::stdout());
+    start.trim().parse().ok()
+}
 
-            let uri = url_ref.parse::<hyper::Uri>().unwrap(); // TODO: check for error
+// None if the file is already in the cache
+async fn start(downloader: &Downloader, file_path: &Path, location: Location, expected: Option<&DatabaseEntry>) -> Result<Option<Source>, DownloadErrorKind> {
+    if let Some(expected) = expected {
+        if expected.verify(file_path).unwrap_or(false) {
+            trace!("{} is already cached", pkg_name(file_path.to_string_lossy()));
+            return Ok(None);
+        }
+    }
 
-            let tmp_path = uri.path().to_string();
-            let url_path = Path::new(&tmp_path);
-            let file_name = url_path.file_name().unwrap().to_str().unwrap(); // TODO: check for error
+    let uri = match location {
+        Location::Http(uri) => uri,
+        Location::Local(path) => {
+            if !path.is_file() {
+                return Err(io::Error::new(io::ErrorKind::NotFound, format!("{} doesn't exist", path.display())).into());
+            }
 
-            let https = HttpsConnector::new();
-            let client = Client::builder().build::<_, hyper::Body>(https);
-            let mut res = client.get(uri).await.unwrap(); // TODO: check for error
-            let expected_length: u64 = res.headers().get("Content-Length").unwrap().to_str().unwrap().parse().unwrap(); // TODO: check for error
+            return Ok(Some(Source::Local(path)));
+        }
+    };
 
-            let pb = mpb.add(ProgressBar::new(expected_length));
-            pb.set_style(download_pg_style());
-            pb.set_prefix(&format!("[{}]", file_name));
+    let part_path = part_path(file_path);
+    let offset = resume_offset(&part_path, expected);
+    if offset > 0 {
+        trace!("Continuing {} at {} bytes", pkg_name(file_path.to_string_lossy()), offset);
+    }
+
+    let res = fetch(downloader, uri.clone(), offset).await;
+
+    // the .part doesn't fit the file on the server anymore, keeping it would fail every retry
+    let restart = offset > 0 && match &res {
+        Ok(res) => res.status() == StatusCode::PARTIAL_CONTENT && content_range_start(res) != Some(offset),
+        Err(DownloadErrorKind::Status(status)) => *status == StatusCode::RANGE_NOT_SATISFIABLE,
+        Err(_) => false,
+    };
+
+    if restart {
+        trace!("{} can't be continued at {} bytes, starting over", pkg_name(file_path.to_string_lossy()), offset);
+        let _ = fs::remove_file(&part_path);
+
+        return Ok(Some(Source::Http(fetch(downloader, uri, 0).await?, 0)));
+    }
+
+    let res = res?;
 
-            let file = save_to_file(file_name, &pb, &mut res, Some(&mpb)).await;
+    // a server without Range support answers with the whole file
+    let offset = if res.status() == StatusCode::PARTIAL_CONTENT { offset } else { 0 };
 
-            mpb.clear().unwrap();
+    Ok(Some(Source::Http(res, offset)))
+}
+
+// logs without tearing the progress bars apart
+fn suspend<F: FnOnce()>(mpb: Option<&MultiProgress>, log: F) {
+    match mpb {
+        // the bars are only drawn again, failing to do so doesn't matter
+        Some(mpb) => {
+            let _ = mpb.clear();
             mpb.set_draw_target(ProgressDrawTarget::hidden());
-            info!("Finished downloading {}", pkg_name(file_name));
+            log();
             mpb.set_draw_target(ProgressDrawTarget::stdout());
+        }
+        None => log(),
+    }
+}
+
+fn tick(mpb: Option<&MultiProgress>) {
+    if let Some(mpb) = mpb {
+        let _ = mpb.tick_and_clear(TickTimeLimit::Indefinite);
+    }
+}
+
+// streams the body into the .part file
+async fn write_body(downloader: &Downloader, part_path: &Path, offset: u64, pb: &ProgressBar, res: &mut Response<Body>, mpb: Option<&MultiProgress>, expected: Option<&DatabaseEntry>) -> Result<(), DownloadErrorKind> {
+    let mut verifier = Verifier::new(expected);
+
+    let mut file = if offset > 0 {
+        let mut part = vec![];
+        File::open(part_path)?.take(offset).read_to_end(&mut part)?;
+        verifier.update(&part)?;
+        pb.inc(offset);
+
+        OpenOptions::new().append(true).open(part_path)?
+    } else {
+        File::create(part_path)?
+    };
+
+    while let Some(next) = within(downloader.read_timeout, res.data()).await? {
+        let chunk = next?;
 
-            mpb.tick_and_clear(TickTimeLimit::Indefinite).unwrap();
+        verifier.update(chunk.bytes())?;
+        file.write_all(chunk.bytes())?;
+        pb.inc(chunk.len() as u64);
+
+        tick(mpb);
+
+        if let Some(throttle) = &downloader.throttle {
+            let delay = throttle.lock().unwrap().delay(chunk.len() as u64);
+            tokio::time::delay_for(delay).await;
+        }
+    }
+
+    file.flush()?;
+
+    verifier.finish()
+}
+
+#[cfg(unix)]
+fn same_filesystem(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn same_filesystem(_: &Path, _: &Path) -> bool {
+    false
+}
+
+// copies a local file into the .part file, a crumb is only hard-linked once it's verified,
+// so the cache never shares a broken file with the mirror
+fn copy_local(source: &Path, part_path: &Path, pb: &ProgressBar, mpb: Option<&MultiProgress>, expected: Option<&DatabaseEntry>) -> Result<(), DownloadErrorKind> {
+    let _ = fs::remove_file(part_path);
+
+    let link = expected.is_some() && same_filesystem(source, part_path.parent().unwrap());
+
+    let mut input = File::open(source)?;
+    let mut output = if link { None } else { Some(File::create(part_path)?) };
+
+    let mut verifier = Verifier::new(expected);
+    let mut buf = vec![0; 64 * 1024];
+
+    loop {
+        let read = input.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+
+        verifier.update(&buf[..read])?;
+        if let Some(output) = &mut output {
+            output.write_all(&buf[..read])?;
+        }
+        pb.inc(read as u64);
+
+        tick(mpb);
+    }
+
+    verifier.finish()?;
 
-            Some(
-                MultiDownloadResult {
-                    name: file_name.to_string(),
-                    file
-                }
-            )
+    if link && fs::hard_link(source, part_path).is_err() {
+        fs::copy(source, part_path)?;
+    }
+
+    Ok(())
+}
+
+async fn save_to_file(downloader: &Downloader, file_name: &str, source: &mut Source, pb: &ProgressBar, mpb: Option<&MultiProgress>, expected: Option<&DatabaseEntry>) -> Result<File, DownloadErrorKind> {
+    let file_path = Path::new(PATH_CACHE).join(file_name);
+    let part_path = part_path(&file_path);
+
+    let result = match source {
+        Source::Http(res, offset) => write_body(downloader, &part_path, *offset, pb, res, mpb, expected).await,
+        Source::Local(path) => copy_local(path, &part_path, pb, mpb, expected),
+    };
+
+    match result {
+        // the connection broke off, keep what we have so the next try can continue
+        Err(err @ DownloadErrorKind::Request(_)) | Err(err @ DownloadErrorKind::Timeout(_)) if expected.is_some() => return Err(err),
+
+        Err(err) => {
+            let _ = fs::remove_file(&part_path); // don't leave a corrupt file in the cache
+            return Err(err);
+        }
+
+        Ok(()) => fs::rename(&part_path, &file_path)?
+    }
+
+    if log::max_level() == log::Level::Trace {
+        suspend(mpb, || trace!("Saving to {}", pkg_name(file_path.to_string_lossy())));
+    }
+
+    Ok(File::open(file_path)?)
+}
+
+// only failures that could go away on their own are tried again,
+// a wrong checksum won't get any better by asking the same mirror again
+fn retryable(kind: &DownloadErrorKind) -> bool {
+    match kind {
+        DownloadErrorKind::Request(_) | DownloadErrorKind::Timeout(_) => true,
+        DownloadErrorKind::Status(status) => status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS,
+        _ => false,
+    }
+}
+
+impl Downloader {
+    // Fails if a CA bundle can't be read or the proxy is invalid
+    pub fn new(network: &Network) -> crate::error::Result<Downloader> {
+        let connector = Connector::new(network)?;
+
+        let throttle = network.bandwidth_limit.filter(|limit| *limit > 0).map(|limit| Arc::new(Mutex::new(Throttle {
+            bytes_per_second: limit * 1024,
+            start: Instant::now(),
+            received: 0,
+        })));
+
+        Ok(Downloader {
+            client: Client::builder().build(connector.clone()),
+            connector,
+
+            parallel_downloads: network.parallel_downloads.unwrap_or(DEFAULT_PARALLEL_DOWNLOADS).max(1),
+            retries: network.retries.unwrap_or(DEFAULT_RETRIES),
+            read_timeout: Duration::from_secs(network.read_timeout.unwrap_or(DEFAULT_READ_TIMEOUT)),
+            user_agent: network.user_agent.clone().unwrap_or_else(|| format!("bread/{}", env!("CARGO_PKG_VERSION"))),
+
+            throttle,
+        })
+    }
+
+    async fn attempt(&self, uri: &str, file_name: &str, expected: Option<&DatabaseEntry>, mpb: Option<&MultiProgress>) -> Result<File, DownloadErrorKind> {
+        let file_path = Path::new(PATH_CACHE).join(file_name);
+
+        let mut source = match start(self, &file_path, locate(uri)?, expected).await? {
+            Some(source) => source,
+            None => return Ok(File::open(file_path)?),
+        };
+
+        let pb = progress_bar(file_name, &source, expected);
+        let pb = match mpb {
+            Some(mpb) => mpb.add(pb),
+            None => pb,
+        };
+
+        let file = save_to_file(self, file_name, &mut source, &pb, mpb, expected).await;
+
+        pb.finish_and_clear();
+
+        file
+    }
+
+    async fn download(&self, uri: &str, expected: Option<&DatabaseEntry>, mpb: Option<&MultiProgress>) -> Result<File, DownloadError> {
+        let file_name = match file_name(uri) {
+            Ok(file_name) => file_name,
+            Err(kind) => return Err(DownloadError {
+                name: expected.map_or_else(|| uri.to_string(), |e| e.name.clone()),
+                uri: uri.to_string(),
+                attempts: 0,
+                kind
+            }),
         };
 
-        futures.push(future);
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+
+            let kind = match self.attempt(uri, &file_name, expected, mpb).await {
+                Ok(file) => return Ok(file),
+                Err(kind) => kind,
+            };
+
+            if attempts > self.retries || !retryable(&kind) {
+                return Err(DownloadError {
+                    name: expected.map_or(file_name, |e| e.name.clone()),
+                    uri: uri.to_string(),
+                    attempts,
+                    kind
+                });
+            }
+
+            let delay = Duration::from_secs(1 << (attempts - 1).min(6));
+            suspend(mpb, || warn!("Downloading {} failed, {}, trying again in {}s", pkg_name(&file_name), kind, delay.as_secs()));
+
+            tokio::time::delay_for(delay).await;
+        }
+    }
+
+    // Downloads uri into the cache, with an expected entry its size and checksum are verified
+    pub async fn download_file<S: AsRef<str>>(&self, uri: S, expected: Option<&DatabaseEntry>) -> Result<File, DownloadError> {
+        let uri = uri.as_ref();
+
+        trace!("Downloading {}", pkg_name(uri));
+
+        let file = self.download(uri, expected, None).await?;
+
+        trace!("Finished downloading {}", pkg_name(uri));
+
+        Ok(file)
+    }
+
+    // Downloads every uri, see download_file. the results are in the same order as the downloads
+    pub async fn download_files<S: AsRef<str>>(&self, downloads: Vec<(S, Option<&DatabaseEntry>)>) -> Vec<Result<MultiDownloadResult, DownloadError>> {
+        let mpb = &MultiProgress::new();
+
+        let futures = downloads.into_iter().map(|(uri, expected)| async move {
+            let uri = uri.as_ref();
+
+            suspend(Some(mpb), || trace!("Downloading {}", pkg_name(uri)));
+
+            let file = self.download(uri, expected, Some(mpb)).await?;
+            let name = file_name(uri).unwrap_or_else(|_| uri.to_string());
+
+            suspend(Some(mpb), || info!("Finished downloading {}", pkg_name(&name)));
+            tick(Some(mpb));
+
+            Ok(MultiDownloadResult { name, file })
+        });
+
+        let results = stream::iter(futures)
+            .buffered(self.parallel_downloads)
+            .collect()
+            .await;
+
+        let _ = mpb.join_and_clear(); // lets join them.
+
+        info!("Finished downloading...");
+
+        results
     }
+}
+
+#[test]
+fn redirect_locations() {
+    let base = "https://mirror.mempler.de/leopard/crumbs/x86_64/bash@5.0.crumb".parse::<Uri>().unwrap();
+    let redirect = |location: &str| {
+        let res = Response::builder().status(StatusCode::FOUND).header(LOCATION, location).body(Body::empty()).unwrap();
+        redirect_target(&base, &res).map(|uri| uri.to_string())
+    };
+
+    assert_eq!("https://cdn.mempler.de/bash@5.0.crumb", redirect("https://cdn.mempler.de/bash@5.0.crumb").unwrap());
+    assert_eq!("https://mirror.mempler.de/pool/bash@5.0.crumb", redirect("/pool/bash@5.0.crumb").unwrap());
+    assert_eq!("https://mirror.mempler.de/leopard/crumbs/x86_64/old/bash@5.0.crumb", redirect("old/bash@5.0.crumb").unwrap());
+
+    let res = Response::builder().status(StatusCode::FOUND).body(Body::empty()).unwrap();
+    assert!(redirect_target(&base, &res).is_err());
+}
+
+#[test]
+fn content_ranges() {
+    let range = |value: &str| {
+        let res = Response::builder().status(StatusCode::PARTIAL_CONTENT).header(CONTENT_RANGE, value).body(Body::empty()).unwrap();
+        content_range_start(&res)
+    };
+
+    assert_eq!(Some(1024), range("bytes 1024-2047/2048"));
+    assert_eq!(Some(0), range("bytes 0-2047/*"));
+    assert_eq!(None, range("bytes */2048"));
+    assert_eq!(None, range("items 1024-2047/2048"));
+    assert_eq!(None, content_range_start(&Response::new(Body::empty())));
+}
+
+#[test]
+fn local_locations() {
+    let local = |uri: &str| match locate(uri).unwrap() {
+        Location::Local(path) => Some(path),
+        Location::Http(_) => None,
+    };
+
+    assert_eq!(Some(PathBuf::from("/srv/mirror/leopard.db.gz")), local("file:///srv/mirror/leopard.db.gz"));
+    assert_eq!(Some(PathBuf::from("/media/usb/leopard.db.gz")), local("/media/usb/leopard.db.gz"));
+    assert_eq!(Some(PathBuf::from("./mirror/leopard.db.gz")), local("./mirror/leopard.db.gz"));
+    assert_eq!(None, local("https://mirror.mempler.de/leopard.db.gz"));
+
+    assert_eq!("bash@5.0.crumb", file_name("file:///srv/mirror/crumbs/x86_64/bash@5.0.crumb").unwrap());
+    assert_eq!("bash@5.0.crumb", file_name("https://mirror.mempler.de/crumbs/x86_64/bash@5.0.crumb").unwrap());
+
+    // malformed mirrors are errors, not panics
+    assert!(matches!(locate("mirror.mempler.de/leopard.db.gz"), Err(DownloadErrorKind::Invalid(_))));
+    assert!(matches!(locate("https://mirror.mempler.de/a b"), Err(DownloadErrorKind::Invalid(_))));
+    assert!(matches!(file_name("https://mirror.mempler.de/"), Err(DownloadErrorKind::Invalid(_))));
+}
+
+#[test]
+fn proxied_http_authorizes() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    let mut runtime = tokio::runtime::Runtime::new().unwrap();
+    let request = runtime.block_on(async {
+        // not every sandbox has a loopback to listen on
+        let mut listener = match TcpListener::bind("127.0.0.1:0").await {
+            Ok(listener) => listener,
+            Err(err) => {
+                eprintln!("skipping, can't listen on 127.0.0.1: {}", err);
+                return None;
+            }
+        };
+
+        let network = Network {
+            proxy: Some(format!("http://bread:crumbs@{}", listener.local_addr().unwrap())),
+            ..Network::default()
+        };
+
+        // a proxy which answers everything itself and hands back what it was asked
+        let proxy = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = vec![];
+
+            while !request.ends_with(b"\r\n\r\n") {
+                let mut byte = [0];
+                socket.read_exact(&mut byte).await.unwrap();
+                request.push(byte[0]);
+            }
+
+            socket.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").await.unwrap();
+            String::from_utf8_lossy(&request).to_lowercase()
+        });
 
-    let results = futures::future::join_all(futures).await; // join the remaining futures
+        let downloader = Downloader::new(&network).unwrap();
+        let uri = "http://mirror.mempler.de/leopard.db.gz".parse().unwrap();
+        fetch(&downloader, uri, 0).await.unwrap();
 
-    mpb.join_and_clear().unwrap(); // lets join them.
+        Some(proxy.await.unwrap())
+    });
 
-    info!("Finished downloading...");
+    let request = match request {
+        Some(request) => request,
+        None => return,
+    };
 
-    results
+    assert!(request.starts_with("get http://mirror.mempler.de/leopard.db.gz http/1.1\r\n"), "{}", request);
+    assert!(request.contains("proxy-authorization: basic ynjlywq6y3j1bwjz\r\n"), "{}", request);
 }
@@ -0,0 +1,78 @@
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+use hyper::StatusCode;
+
+#[derive(Debug)]
+pub enum DownloadErrorKind {
+    // the connection failed or the response broke off
+    Request(hyper::Error),
+    Timeout(Duration),
+    Status(StatusCode),
+    Redirect(String),
+
+    // a mirror uri or a [network] setting bread can't build a request from
+    Invalid(String),
+
+    // more than expected is aborted right away, less is noticed at the end
+    Size { expected: u64, received: u64 },
+    Checksum { expected: String, actual: String },
+
+    Io(io::Error),
+}
+
+#[derive(Debug)]
+pub struct DownloadError {
+    // the package, or the file if it doesn't belong to one
+    pub name: String,
+    pub uri: String,
+    pub attempts: u32,
+    pub kind: DownloadErrorKind,
+}
+
+impl fmt::Display for DownloadErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DownloadErrorKind::Request(err) => write!(f, "{}", err),
+            DownloadErrorKind::Timeout(timeout) => write!(f, "nothing was received for {}s", timeout.as_secs()),
+            DownloadErrorKind::Status(status) => write!(f, "the mirror answered with {}", status),
+            DownloadErrorKind::Redirect(reason) => write!(f, "bad redirect, {}", reason),
+            DownloadErrorKind::Invalid(reason) => write!(f, "{}", reason),
+
+            DownloadErrorKind::Size { expected, received } if received > expected =>
+                write!(f, "received more than the expected {} bytes", expected),
+            DownloadErrorKind::Size { expected, received } =>
+                write!(f, "received {} of the expected {} bytes", received, expected),
+
+            DownloadErrorKind::Checksum { expected, actual } =>
+                write!(f, "checksum mismatch, expected {} but got {}", expected, actual),
+
+            DownloadErrorKind::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.attempts > 1 {
+            write!(f, "Failed to download {} from {} after {} attempts: {}", self.name, self.uri, self.attempts, self.kind)
+        } else {
+            write!(f, "Failed to download {} from {}: {}", self.name, self.uri, self.kind)
+        }
+    }
+}
+
+impl std::error::Error for DownloadError {}
+
+impl From<io::Error> for DownloadErrorKind {
+    fn from(err: io::Error) -> DownloadErrorKind {
+        DownloadErrorKind::Io(err)
+    }
+}
+
+impl From<hyper::Error> for DownloadErrorKind {
+    fn from(err: hyper::Error) -> DownloadErrorKind {
+        DownloadErrorKind::Request(err)
+    }
+}
@@ -1,3 +1,7 @@
+mod connector;
 mod download;
+mod error;
 
-pub use download::{download_file, download_files, MultiDownloadResult};
+pub use connector::{Connector, Proxy};
+pub use download::{Downloader, MultiDownloadResult};
+pub use error::{DownloadError, DownloadErrorKind};
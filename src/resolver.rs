@@ -0,0 +1,421 @@
+// Resolves the [dependencies] of the requested packages into an install plan.
+//
+// a requirement looks like one of those
+//   "*"              any version
+//   "=1.2.3"         exactly 1.2.3
+//   "^1.2"           >=1.2, <2.0     (a bare "1.2" means the same)
+//   "~1.2.3"         >=1.2.3, <1.3
+//   ">=1.0, <1.5"    a range, every comparator has to match
+
+use std::collections::btree_map::BTreeMap;
+use std::collections::btree_set::BTreeSet;
+use std::fmt;
+
+use crate::database::{Database, DatabaseEntry};
+use crate::version::Version;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Exact,
+    Greater,
+    GreaterEq,
+    Less,
+    LessEq,
+    Tilde,
+    Caret,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Comparator {
+    op: Op,
+    version: Version,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VersionReq {
+    comparators: Vec<Comparator>,
+}
+
+impl Comparator {
+    fn parse(data: &str) -> Option<Comparator> {
+        let data = data.trim();
+
+        let (op, version) =
+            if let Some(version) = data.strip_prefix(">=") { (Op::GreaterEq, version) }
+            else if let Some(version) = data.strip_prefix("<=") { (Op::LessEq, version) }
+            else if let Some(version) = data.strip_prefix('>') { (Op::Greater, version) }
+            else if let Some(version) = data.strip_prefix('<') { (Op::Less, version) }
+            else if let Some(version) = data.strip_prefix('=') { (Op::Exact, version) }
+            else if let Some(version) = data.strip_prefix('~') { (Op::Tilde, version) }
+            else if let Some(version) = data.strip_prefix('^') { (Op::Caret, version) }
+            else { (Op::Caret, data) };
+
+        Some(Comparator {
+            op,
+            version: version.parse().ok()?,
+        })
+    }
+
+    // the exclusive upper bound of ^ and ~
+    fn upper_bound(&self) -> Version {
+        let components = &self.version.components;
+
+        let bump_at = match self.op {
+            Op::Tilde => if components.len() > 1 { 1 } else { 0 },
+            _ => components.iter()
+                .position(|c| *c != 0)
+                .unwrap_or(components.len() - 1),
+        };
+
+        let mut upper: Vec<u64> = components[..=bump_at].to_vec();
+        upper[bump_at] += 1;
+
+        Version {
+            epoch: self.version.epoch,
+            components: upper,
+            pre_release: None,
+            revision: 0,
+        }
+    }
+
+    fn matches(&self, version: &Version) -> bool {
+        match self.op {
+            Op::Exact => *version == self.version,
+            Op::Greater => *version > self.version,
+            Op::GreaterEq => *version >= self.version,
+            Op::Less => *version < self.version,
+            Op::LessEq => *version <= self.version,
+            Op::Tilde | Op::Caret => *version >= self.version && *version < self.upper_bound(),
+        }
+    }
+}
+
+impl VersionReq {
+    pub fn parse<S: AsRef<str>>(data: S) -> Option<VersionReq> {
+        let data = data.as_ref().trim();
+
+        if data == "*" || data.is_empty() {
+            return Some(VersionReq { comparators: vec![] });
+        }
+
+        let comparators = data.split(',')
+            .map(Comparator::parse)
+            .collect::<Option<Vec<Comparator>>>()?;
+
+        Some(VersionReq { comparators })
+    }
+
+    pub fn matches(&self, version: &Version) -> bool {
+        self.comparators.iter().all(|c| c.matches(version))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResolveError {
+    // a requirement string that couldn't be parsed
+    InvalidRequirement { name: String, requirement: String, required_by: String },
+
+    // no database knows about this package
+    NotFound { name: String, required_by: String },
+
+    // the package exists, but no version satisfies every requirement
+    Unsatisfiable { name: String, requirements: Vec<(String, String)>, available: Vec<String> },
+
+    // the packages depend on each other, there is no order to install them in
+    Cycle(Vec<String>),
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResolveError::InvalidRequirement { name, requirement, required_by } =>
+                write!(f, "{} requires {} \"{}\", which is not a valid version requirement", required_by, name, requirement),
+
+            ResolveError::NotFound { name, required_by } =>
+                write!(f, "{} requires {}, which is not in any database", required_by, name),
+
+            ResolveError::Unsatisfiable { name, requirements, available } => {
+                writeln!(f, "no version of {} satisfies every requirement", name)?;
+
+                for (required_by, requirement) in requirements {
+                    writeln!(f, "  {} requires {} \"{}\"", required_by, name, requirement)?;
+                }
+
+                write!(f, "  available versions: {}", available.join(", "))
+            }
+
+            ResolveError::Cycle(path) =>
+                write!(f, "dependency cycle: {}", path.join(" -> ")),
+        }
+    }
+}
+
+// the name used for packages the user asked for
+const REQUESTED: &str = "(requested)";
+
+struct Resolver<'a> {
+    databases: &'a [Database],
+
+    // name -> [(required_by, requirement)]
+    requirements: BTreeMap<String, Vec<(String, String)>>,
+    selected: BTreeMap<String, DatabaseEntry>,
+}
+
+impl<'a> Resolver<'a> {
+    // every version of a package for this architecture across all databases, newest first.
+    // if two databases carry the same version, the first one wins.
+    fn candidates(&self, name: &str) -> Vec<&'a DatabaseEntry> {
+        let mut candidates: Vec<&DatabaseEntry> = vec![];
+
+        for db in self.databases {
+            for entry in db.entries.iter().filter(|e| e.name == name && e.is_for_architecture(std::env::consts::ARCH)) {
+                if !candidates.iter().any(|c| c.version == entry.version) {
+                    candidates.push(entry);
+                }
+            }
+        }
+
+        candidates.sort_by(|a, b| b.version.cmp(&a.version));
+
+        candidates
+    }
+
+    fn satisfies(&self, name: &str, version: &Version) -> bool {
+        self.requirements.get(name)
+            .map(|reqs| reqs.iter().all(|(_, req)| VersionReq::parse(req).map(|r| r.matches(version)).unwrap_or(false)))
+            .unwrap_or(true)
+    }
+
+    fn require(&mut self, name: &str, requirement: &str, required_by: &str) -> Result<(), ResolveError> {
+        if VersionReq::parse(requirement).is_none() {
+            return Err(ResolveError::InvalidRequirement {
+                name: name.to_string(),
+                requirement: requirement.to_string(),
+                required_by: required_by.to_string(),
+            });
+        }
+
+        self.requirements.entry(name.to_string())
+            .or_default()
+            .push((required_by.to_string(), requirement.to_string()));
+
+        Ok(())
+    }
+
+    fn unresolved(&self) -> Option<String> {
+        self.requirements.keys()
+            .find(|name| !self.selected.contains_key(*name))
+            .cloned()
+    }
+
+    // depth first search with backtracking, tries the newest versions first
+    fn resolve(&mut self) -> Result<(), ResolveError> {
+        let name = match self.unresolved() {
+            Some(name) => name,
+            None => return Ok(()),
+        };
+
+        let candidates = self.candidates(&name);
+
+        if candidates.is_empty() {
+            let required_by = self.requirements[&name].iter()
+                .map(|(by, _)| by.clone())
+                .collect::<Vec<String>>()
+                .join(", ");
+
+            return Err(ResolveError::NotFound { name, required_by });
+        }
+
+        let mut last_error = ResolveError::Unsatisfiable {
+            name: name.clone(),
+            requirements: self.requirements[&name].clone(),
+            available: candidates.iter().map(|c| c.version.to_string()).collect(),
+        };
+
+        for candidate in candidates {
+            if !self.satisfies(&name, &candidate.version) {
+                continue;
+            }
+
+            let saved_requirements = self.requirements.clone();
+            self.selected.insert(name.clone(), candidate.clone());
+
+            let mut result = Ok(());
+            for (dependency, requirement) in &candidate.dependencies {
+                result = self.require(dependency, requirement, &candidate.name);
+                if result.is_err() {
+                    break;
+                }
+
+                if let Some(selected) = self.selected.get(dependency) {
+                    if !self.satisfies(dependency, &selected.version) {
+                        result = Err(ResolveError::Unsatisfiable {
+                            name: dependency.clone(),
+                            requirements: self.requirements[dependency].clone(),
+                            available: self.candidates(dependency).iter().map(|c| c.version.to_string()).collect(),
+                        });
+                        break;
+                    }
+                }
+            }
+
+            match result.and_then(|_| self.resolve()) {
+                Ok(()) => return Ok(()),
+                Err(err) => last_error = err,
+            }
+
+            self.selected.remove(&name);
+            self.requirements = saved_requirements;
+        }
+
+        Err(last_error)
+    }
+
+    // orders the selected packages so every dependency comes before its dependents
+    fn order(&self) -> Result<Vec<DatabaseEntry>, ResolveError> {
+        let mut plan = vec![];
+        let mut done = BTreeSet::new();
+
+        for name in self.selected.keys() {
+            self.visit(name, &mut vec![], &mut done, &mut plan)?;
+        }
+
+        Ok(plan)
+    }
+
+    fn visit(&self, name: &str, path: &mut Vec<String>, done: &mut BTreeSet<String>, plan: &mut Vec<DatabaseEntry>) -> Result<(), ResolveError> {
+        if done.contains(name) {
+            return Ok(());
+        }
+
+        if let Some(start) = path.iter().position(|n| n == name) {
+            let mut cycle = path[start..].to_vec();
+            cycle.push(name.to_string());
+            return Err(ResolveError::Cycle(cycle));
+        }
+
+        let entry = &self.selected[name];
+
+        path.push(name.to_string());
+        for dependency in entry.dependencies.keys() {
+            self.visit(dependency, path, done, plan)?;
+        }
+        path.pop();
+
+        done.insert(name.to_string());
+        plan.push(entry.clone());
+
+        Ok(())
+    }
+}
+
+// Resolves the requested packages and all of their dependencies,
+// returns the install plan with dependencies first
+pub fn resolve<S: AsRef<str>>(databases: &[Database], requested: &[S]) -> Result<Vec<DatabaseEntry>, ResolveError> {
+    let requested: Vec<(&str, &str)> = requested.iter().map(|name| (name.as_ref(), "*")).collect();
+
+    resolve_requirements(databases, &requested)
+}
+
+// Like resolve, but every requested package comes with a version requirement,
+// upgrade uses it to keep frozen packages at their version
+pub fn resolve_requirements<S: AsRef<str>, R: AsRef<str>>(databases: &[Database], requested: &[(S, R)]) -> Result<Vec<DatabaseEntry>, ResolveError> {
+    let mut resolver = Resolver {
+        databases,
+        requirements: BTreeMap::new(),
+        selected: BTreeMap::new(),
+    };
+
+    for (name, requirement) in requested {
+        resolver.require(name.as_ref(), requirement.as_ref(), REQUESTED)?;
+    }
+
+    resolver.resolve()?;
+    resolver.order()
+}
+
+// name, version and the dependencies with their requirements
+#[cfg(test)]
+type TestEntry<'a> = (&'a str, &'a str, &'a [(&'a str, &'a str)]);
+
+#[cfg(test)]
+fn test_database(entries: &[TestEntry]) -> Vec<Database> {
+    let entries = entries.iter()
+        .map(|(name, version, dependencies)| DatabaseEntry {
+            name: name.to_string(),
+            version: version.parse().unwrap(),
+            dependencies: dependencies.iter().map(|(n, r)| (n.to_string(), r.to_string())).collect(),
+            ..DatabaseEntry::new()
+        })
+        .collect();
+
+    vec![Database { name: "test".to_string(), entries }]
+}
+
+#[test]
+fn version_requirements() {
+    let matches = |req: &str, version: &str| VersionReq::parse(req).unwrap().matches(&version.parse().unwrap());
+
+    assert!(matches("^1.2", "1.9.3"));
+    assert!(!matches("^1.2", "2.0"));
+    assert!(!matches("^0.2.3", "0.3.0"));
+    assert!(matches("~1.2.3", "1.2.9"));
+    assert!(!matches("~1.2.3", "1.3"));
+    assert!(matches(">=1.0, <1.5", "1.4.99"));
+    assert!(!matches(">=1.0, <1.5", "1.5"));
+    assert!(matches("=8.32", "8.32.0"));
+    assert!(matches("*", "0.1"));
+    assert!(VersionReq::parse("^one").is_none());
+}
+
+#[test]
+fn resolve_transitive_dependencies() {
+    let databases = test_database(&[
+        ("coreutils", "8.32", &[("libc", "^1.0"), ("acl", "~2.2")]),
+        ("acl", "2.2.53", &[("libc", ">=1.1")]),
+        ("acl", "2.3.1", &[]),
+        ("libc", "1.2", &[]),
+        ("libc", "1.0", &[]),
+        ("libc", "2.0", &[]),
+    ]);
+
+    let plan = resolve(&databases, &["coreutils"]).unwrap();
+    let plan: Vec<String> = plan.iter().map(|e| format!("{}@{}", e.name, e.version)).collect();
+
+    assert_eq!(vec!["libc@1.2", "acl@2.2.53", "coreutils@8.32"], plan);
+
+    // entries of other architectures are never picked
+    let mut databases = test_database(&[("libc", "1.0", &[]), ("libc", "1.3", &[]), ("libc", "1.4", &[])]);
+    databases[0].entries[1].architecture = Some("any".to_string());
+    databases[0].entries[2].architecture = Some(format!("not-{}", std::env::consts::ARCH));
+
+    assert_eq!("1.3", resolve(&databases, &["libc"]).unwrap()[0].version.to_string());
+}
+
+#[test]
+fn resolve_errors() {
+    let databases = test_database(&[
+        ("a", "1.0", &[("b", "^1.0")]),
+        ("b", "1.0", &[("a", "*")]),
+        ("c", "1.0", &[("b", "^2.0")]),
+        ("d", "1.0", &[("missing", "*")]),
+    ]);
+
+    match resolve(&databases, &["a"]) {
+        Err(ResolveError::Cycle(cycle)) => assert_eq!(vec!["a", "b", "a"], cycle),
+        result => panic!("expected a cycle, got {:?}", result),
+    }
+
+    match resolve(&databases, &["c"]) {
+        Err(ResolveError::Unsatisfiable { name, .. }) => assert_eq!("b", name),
+        result => panic!("expected b to be unsatisfiable, got {:?}", result),
+    }
+
+    match resolve(&databases, &["d"]) {
+        Err(ResolveError::NotFound { name, required_by }) => {
+            assert_eq!("missing", name);
+            assert_eq!("d", required_by);
+        }
+        result => panic!("expected missing to be not found, got {:?}", result),
+    }
+}
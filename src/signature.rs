@@ -0,0 +1,200 @@
+// databases and crumbs are signed with Ed25519, the signature is detached and lives next to the file
+//
+//   leopard.db.gz      leopard.db.gz.sig
+//   coreutils@8.32.crumb   coreutils@8.32.crumb.sig
+//
+// a .sig file is the hex encoded signature of the SHA512 of the file,
+// so big crumbs don't have to be read into memory at once.
+//
+// the keyring is /etc/bread/keys/, every {name}.pub in there is a hex encoded public key.
+// `bread kitchen keygen` creates {name}.key (the secret) and {name}.pub.
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::convert::TryFrom;
+
+use ed25519_dalek::{Keypair, PublicKey, Signature, Signer, Verifier};
+use rand::rngs::OsRng;
+use sha2::{Sha512, Digest};
+
+use crate::style::pkg_name;
+use crate::constants::PATH_CONFIGS;
+use crate::error::{BreadError, IoContext, Result};
+
+pub struct Keyring {
+    // key name -> public key
+    pub keys: Vec<(String, PublicKey)>,
+}
+
+fn file_digest<P: AsRef<Path>>(path: P) -> Result<Vec<u8>> {
+    let path = path.as_ref();
+    let mut file = File::open(path).at(path)?;
+
+    let mut sha512 = Sha512::default();
+    io::copy(&mut file, &mut sha512).at(path)?;
+
+    Ok(sha512.result().to_vec())
+}
+
+pub fn signature_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    let mut path = path.as_ref().as_os_str().to_owned();
+    path.push(".sig");
+
+    PathBuf::from(path)
+}
+
+impl Keyring {
+    // Reads every {name}.pub inside of path, a missing directory is an empty keyring
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Keyring> {
+        let path = path.as_ref();
+        let mut keys = vec![];
+
+        if !path.is_dir() {
+            return Ok(Keyring { keys });
+        }
+
+        let mut files = vec![];
+        for entry in path.read_dir().at(path)? {
+            let file = entry.at(path)?.path();
+
+            if file.extension().map_or(false, |e| e == "pub") {
+                files.push(file);
+            }
+        }
+
+        files.sort();
+
+        for file in files {
+            let name = file.file_stem().unwrap_or_default().to_string_lossy().to_string();
+            let bytes = hex::decode(fs::read_to_string(&file).at(&file)?.trim())
+                .map_err(|err| BreadError::parse(file.to_string_lossy(), err))?;
+
+            let key = PublicKey::from_bytes(&bytes)
+                .map_err(|err| BreadError::parse(file.to_string_lossy(), err))?;
+
+            log::trace!("Trusting key {}", pkg_name(&name));
+            keys.push((name, key));
+        }
+
+        Ok(Keyring { keys })
+    }
+
+    // returns the name of the key which signed the file
+    pub fn verify_file<P: AsRef<Path>, SP: AsRef<Path>>(&self, path: P, signature_path: SP) -> Result<Option<&str>> {
+        let signature_path = signature_path.as_ref();
+        let data = fs::read_to_string(signature_path).at(signature_path)?;
+        let bytes = match hex::decode(data.trim()) {
+            Ok(bytes) => bytes,
+            Err(_) => return Ok(None),
+        };
+
+        let signature = match Signature::try_from(bytes.as_slice()) {
+            Ok(signature) => signature,
+            Err(_) => return Ok(None),
+        };
+
+        let digest = file_digest(path)?;
+
+        Ok(self.keys.iter()
+            .find(|(_, key)| key.verify(&digest, &signature).is_ok())
+            .map(|(name, _)| name.as_str()))
+    }
+}
+
+// The keyring at /etc/bread/keys, None if the signature checks were turned off with --insecure
+pub fn system_keyring(insecure: bool) -> Result<Option<Keyring>> {
+    if insecure {
+        log::warn!("Signatures are not being checked!");
+        return Ok(None);
+    }
+
+    let keyring = Keyring::load(Path::new(PATH_CONFIGS).join("keys"))?;
+    if keyring.keys.is_empty() {
+        log::warn!("There are no keys in {}/keys, nothing will verify", PATH_CONFIGS);
+    }
+
+    Ok(Some(keyring))
+}
+
+// Creates {name}.key and {name}.pub inside of path
+pub fn generate_keypair<P: AsRef<Path>, S: AsRef<str>>(path: P, name: S) -> Result<()> {
+    let path = path.as_ref();
+    let name = name.as_ref();
+
+    let keypair = Keypair::generate(&mut OsRng);
+
+    // an existing key is never replaced, and the secret is only readable by us from the start
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let secret_path = path.join(format!("{}.key", name));
+    let mut secret = options.open(&secret_path).at(&secret_path)?;
+    secret.write_all((hex::encode(&keypair.to_bytes()[..]) + "\n").as_bytes()).at(&secret_path)?;
+
+    let public_path = path.join(format!("{}.pub", name));
+    fs::write(&public_path, hex::encode(keypair.public.as_bytes()) + "\n").at(&public_path)?;
+
+    Ok(())
+}
+
+// Signs a file with the secret key at key_path, writes {file}.sig
+pub fn sign_file<P: AsRef<Path>, K: AsRef<Path>>(path: P, key_path: K) -> Result<PathBuf> {
+    let path = path.as_ref();
+    let key_path = key_path.as_ref();
+
+    let bytes = hex::decode(fs::read_to_string(key_path).at(key_path)?.trim())
+        .map_err(|err| BreadError::parse(key_path.to_string_lossy(), err))?;
+
+    let keypair = Keypair::from_bytes(&bytes)
+        .map_err(|err| BreadError::parse(key_path.to_string_lossy(), err))?;
+
+    let signature = keypair.sign(&file_digest(path)?);
+
+    let sig_path = signature_path(path);
+    fs::write(&sig_path, hex::encode(&signature.to_bytes()[..]) + "\n").at(&sig_path)?;
+
+    Ok(sig_path)
+}
+
+#[test]
+fn sign_and_verify() {
+    let path = std::env::temp_dir().join("bread-sign-and-verify");
+    let keys = path.join("keys");
+    let _ = fs::remove_dir_all(&path);
+    fs::create_dir_all(&keys).unwrap();
+
+    generate_keypair(&keys, "leopard").unwrap();
+    assert!(generate_keypair(&keys, "leopard").is_err());
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        assert_eq!(0o600, fs::metadata(keys.join("leopard.key")).unwrap().permissions().mode() & 0o777);
+    }
+
+    let file = path.join("leopard.db.gz");
+    fs::write(&file, "VERSION_2_0\n").unwrap();
+
+    let sig = sign_file(&file, keys.join("leopard.key")).unwrap();
+    assert_eq!(path.join("leopard.db.gz.sig"), sig);
+
+    // the secret key doesn't belong into the keyring
+    fs::remove_file(keys.join("leopard.key")).unwrap();
+
+    let keyring = Keyring::load(&keys).unwrap();
+    assert_eq!(Some("leopard"), keyring.verify_file(&file, &sig).unwrap());
+
+    fs::write(&file, "VERSION_1_0\n").unwrap();
+    assert_eq!(None, keyring.verify_file(&file, &sig).unwrap());
+
+    assert_eq!(None, Keyring::load(path.join("missing")).unwrap().verify_file(&file, &sig).unwrap());
+
+    fs::remove_dir_all(&path).unwrap();
+}
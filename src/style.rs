@@ -11,6 +11,13 @@ pub fn download_pg_style() -> ProgressStyle {
         .progress_chars("▉▊▋▌▍▎▏ ")
 }
 
+// for downloads where neither the mirror nor the database tells us the size
+pub fn download_spinner_style() -> ProgressStyle {
+    ProgressStyle::default_spinner()
+        .template("{prefix:.red} {spinner:.cyan} {bytes:>10.green}")
+        .tick_chars("▉▊▋▌▍▎▏▎▍▌▋▊▉ ")
+}
+
 pub fn install_pg_style() -> ProgressStyle {
     ProgressStyle::default_bar()
         .template("{prefix:.red} [{wide_bar:0.yellow}] ({eta:>3.yellow}) {percent:>3}%")
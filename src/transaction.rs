@@ -0,0 +1,298 @@
+// installs a set of crumbs as one transaction, like `bread upgrade` does.
+// whatever a step overwrites or leaves behind of the old version is moved into
+// {root}/var/cache/bread/transaction/ first. if a step fails, everything the steps
+// created is deleted and the backup is moved back. scripts can't be undone.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::archive::{normalize, CrumbArchive, EntryKind};
+use crate::constants::PATH_CACHE;
+use crate::crumb::Crumb;
+use crate::installed::InstalledPackage;
+use crate::manifest::{FileType, Manifest, ManifestEntry};
+use crate::style::pkg_name;
+use crate::error::{BreadError, IoContext, Result};
+
+pub struct Transaction {
+    root: PathBuf,
+    backup: PathBuf,
+
+    // relative to the root, the backup keeps the same layout
+    kept: Vec<PathBuf>,
+
+    // files and installed records the steps made, directories which didn't exist before
+    created: Vec<PathBuf>,
+    created_dirs: Vec<PathBuf>,
+}
+
+// moves a file or symlink, copies it if it's on another file system
+fn move_file(from: &Path, to: &Path) -> Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent).at(parent)?;
+    }
+
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+
+    let metadata = fs::symlink_metadata(from).at(from)?;
+    if metadata.file_type().is_symlink() {
+        let target = fs::read_link(from).at(from)?;
+        symlink(&target, to).at(to)?;
+    } else {
+        fs::copy(from, to).at(to)?;
+    }
+
+    fs::remove_file(from).at(from)
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(not(unix))]
+fn symlink(_: &Path, _: &Path) -> std::io::Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Other, "symlinks can only be moved across file systems on unix"))
+}
+
+// the paths below the root the crumb writes to and whether they are directories. crumbs
+// without a manifest are read entry by entry, so nothing they overwrite misses the backup
+fn targets(archive: &CrumbArchive) -> Result<Vec<(PathBuf, bool)>> {
+    if let Some(manifest) = &archive.manifest {
+        return Ok(manifest.entries.iter()
+            .filter_map(|entry| normalize(Path::new(&entry.path)).map(|relative| (relative, entry.file_type == FileType::Directory)))
+            .filter(|(relative, _)| !relative.as_os_str().is_empty())
+            .collect());
+    }
+
+    let mut tar = CrumbArchive::tar(&archive.path)?;
+    let mut targets = vec![];
+
+    for entry in tar.entries().at(&archive.path)? {
+        let entry = entry.at(&archive.path)?;
+
+        if let EntryKind::Payload(relative) = archive.classify(entry.path().at(&archive.path)?) {
+            targets.push((relative, entry.header().entry_type().is_dir()));
+        }
+    }
+
+    Ok(targets)
+}
+
+// whether a file in etc/ differs from what the manifest says, those are the user's
+fn modified_config(root: &Path, relative: &Path, entry: &ManifestEntry) -> Result<bool> {
+    if !relative.starts_with("etc") || entry.file_type != FileType::File {
+        return Ok(false);
+    }
+
+    let path = root.join(relative);
+    let current = ManifestEntry::from_file(&entry.path, &path)?.and_then(|current| current.sha512);
+
+    Ok(current != entry.sha512)
+}
+
+impl Transaction {
+    pub fn new<P: AsRef<Path>>(root: P) -> Result<Transaction> {
+        let root = root.as_ref().to_path_buf();
+        let backup = root.join(PATH_CACHE.trim_start_matches('/')).join("transaction");
+
+        if backup.exists() {
+            return Err(BreadError::Config(format!("{} is left from an interrupted transaction, move its files back or delete it", backup.display())));
+        }
+
+        fs::create_dir_all(&backup).at(&backup)?;
+
+        Ok(Transaction {
+            root,
+            backup,
+            kept: vec![],
+            created: vec![],
+            created_dirs: vec![],
+        })
+    }
+
+    // moves a file out of the way into the backup, only the first version of it is kept
+    fn keep(&mut self, relative: &Path) -> Result<()> {
+        let path = self.root.join(relative);
+        if self.kept.iter().any(|kept| kept == relative) || fs::symlink_metadata(&path).is_err() {
+            return Ok(());
+        }
+
+        move_file(&path, &self.backup.join(relative))?;
+        self.kept.push(relative.to_path_buf());
+
+        Ok(())
+    }
+
+    // Installs crumb in place of old, the files of old the crumb doesn't have anymore are removed
+    pub async fn install<P: AsRef<Path>>(&mut self, crumb: P, old: Option<&InstalledPackage>) -> Result<()> {
+        let crumb = crumb.as_ref();
+        let archive = CrumbArchive::open(crumb)?;
+        let targets = targets(&archive)?;
+        let old_manifest = old.and_then(|old| old.manifest.clone()).unwrap_or_default();
+
+        for (relative, is_dir) in &targets {
+            let path = self.root.join(relative);
+            match fs::symlink_metadata(&path) {
+                Ok(metadata) if metadata.is_dir() => {}
+
+                Ok(_) => {
+                    if let Some(old_entry) = old_manifest.get(relative.to_string_lossy()) {
+                        if modified_config(&self.root, relative, old_entry)? {
+                            let saved = path.with_file_name(format!("{}.bread-old", path.file_name().unwrap_or_default().to_string_lossy()));
+                            log::warn!("{} was modified, the new version replaces it and it's saved as {}", path.display(), saved.display());
+                            fs::copy(&path, &saved).at(&saved)?;
+                            self.created.push(saved);
+                        }
+                    }
+
+                    self.keep(relative)?;
+                    self.created.push(path);
+                }
+
+                Err(_) if *is_dir => self.created_dirs.push(path),
+                Err(_) => self.created.push(path),
+            }
+        }
+
+        if let Some(old) = old {
+            self.remove_stale(old, &old_manifest, &targets)?;
+        }
+
+        self.created.push(archive.installed_path(&self.root)?);
+        Crumb::install_package_in(crumb, &self.root).await
+    }
+
+    // moves the files and the installed record of old the new version doesn't have into the backup
+    fn remove_stale(&mut self, old: &InstalledPackage, old_manifest: &Manifest, targets: &[(PathBuf, bool)]) -> Result<()> {
+        // children before their parents, so the directories are empty once we get to them
+        for entry in old_manifest.entries.iter().rev() {
+            let relative = match normalize(Path::new(&entry.path)) {
+                Some(relative) if !relative.as_os_str().is_empty() => relative,
+                _ => continue,
+            };
+
+            if targets.iter().any(|(target, _)| *target == relative) {
+                continue;
+            }
+
+            if entry.file_type == FileType::Directory {
+                let _ = fs::remove_dir(self.root.join(&relative));
+            } else if modified_config(&self.root, &relative, entry)? {
+                log::warn!("Keeping {}, it was modified", relative.display());
+            } else {
+                self.keep(&relative)?;
+            }
+        }
+
+        let record = old.path.strip_prefix(&self.root).unwrap_or(&old.path).to_path_buf();
+        let mut files = vec![];
+        walk_files(&old.path, &record, &mut files)?;
+
+        for file in files {
+            self.keep(&file)?;
+        }
+
+        Ok(())
+    }
+
+    // Everything went through, the backup isn't needed anymore
+    pub fn commit(self) -> Result<()> {
+        fs::remove_dir_all(&self.backup).at(&self.backup)
+    }
+
+    // Deletes what the steps created and moves the backup back
+    pub fn rollback(self) -> Result<()> {
+        log::warn!("Rolling back {} change(s)", self.created.len() + self.kept.len());
+
+        for path in self.created.iter().rev() {
+            match fs::symlink_metadata(path) {
+                Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path).at(path)?,
+                Ok(_) => fs::remove_file(path).at(path)?,
+                Err(_) => {}
+            }
+        }
+
+        for path in self.created_dirs.iter().rev() {
+            let _ = fs::remove_dir(path);
+        }
+
+        for relative in self.kept.iter().rev() {
+            log::trace!("Restoring {}", pkg_name(relative.to_string_lossy()));
+            move_file(&self.backup.join(relative), &self.root.join(relative))?;
+        }
+
+        fs::remove_dir_all(&self.backup).at(&self.backup)
+    }
+}
+
+// every file below path, named relative to name
+fn walk_files(path: &Path, name: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(path).at(path)? {
+        let entry = entry.at(path)?;
+        let entry_name = name.join(entry.file_name());
+
+        if entry.file_type().at(entry.path())?.is_dir() {
+            walk_files(&entry.path(), &entry_name, files)?;
+        } else {
+            files.push(entry_name);
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn upgrade_and_roll_back() {
+    use crate::installed::installed_dir;
+
+    let path = std::env::temp_dir().join("bread-upgrade-and-roll-back");
+    let root = path.join("root");
+    let _ = fs::remove_dir_all(&path);
+
+    let bake = |version: &str, files: &[&str], install: &str| {
+        let package = path.join(version);
+        fs::create_dir_all(package.join("source/usr/bin")).unwrap();
+        for file in files {
+            fs::write(package.join("source/usr/bin").join(file), version).unwrap();
+        }
+
+        fs::write(package.join("crumb.toml"), format!("[package]\nname = \"hello\"\nversion = \"{}\"\n[scripts]\ninstall = \"{}\"\n", version, install)).unwrap();
+        Crumb::bake_package(&package).unwrap()
+    };
+
+    let old_crumb = bake("1.0", &["hello", "hi"], "true");
+
+    // without a manifest the archive itself says what gets overwritten
+    let mut archive = CrumbArchive::open(&old_crumb).unwrap();
+    let from_manifest = targets(&archive).unwrap();
+    archive.manifest = None;
+    assert_eq!(from_manifest.len(), targets(&archive).unwrap().len());
+    assert!(targets(&archive).unwrap().contains(&(PathBuf::from("usr/bin/hi"), false)));
+
+    futures::executor::block_on(Crumb::install_package_in(&old_crumb, &root)).unwrap();
+    let old = InstalledPackage::load(installed_dir(&root).join("hello")).unwrap();
+
+    // the install script of 1.1 fails, so 1.0 comes back
+    let broken = bake("1.1", &["hello"], "false");
+    let mut transaction = Transaction::new(&root).unwrap();
+    assert!(futures::executor::block_on(transaction.install(&broken, Some(&old))).is_err());
+    transaction.rollback().unwrap();
+
+    assert_eq!("1.0", fs::read_to_string(root.join("usr/bin/hello")).unwrap());
+    assert!(root.join("usr/bin/hi").is_file());
+    assert_eq!("1.0", InstalledPackage::load(installed_dir(&root).join("hello")).unwrap().info.package.version.to_string());
+
+    let new = bake("1.2", &["hello"], "true");
+    let mut transaction = Transaction::new(&root).unwrap();
+    futures::executor::block_on(transaction.install(&new, Some(&old))).unwrap();
+    transaction.commit().unwrap();
+
+    assert_eq!("1.2", fs::read_to_string(root.join("usr/bin/hello")).unwrap());
+    assert!(!root.join("usr/bin/hi").exists());
+    assert!(!root.join("var/cache/bread/transaction").exists());
+
+    fs::remove_dir_all(&path).unwrap();
+}
@@ -0,0 +1,230 @@
+// `bread upgrade` compares the installed packages with the databases, packages in
+// [frozen-crumbs] of config.toml stay at their version unless they are forced.
+// the new versions and the dependencies they pull in are installed as one transaction.
+
+use std::collections::btree_map::BTreeMap;
+use std::path::Path;
+
+use crate::config::Config;
+use crate::constants::PATH_CONFIGS;
+use crate::crumb::Crumb;
+use crate::database::{Database, DatabaseEntry};
+use crate::installed::InstalledPackage;
+use crate::resolver::resolve_requirements;
+use crate::style::pkg_name;
+use crate::transaction::Transaction;
+use crate::version::Version;
+use crate::error::{BreadError, Result};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Upgrade {
+    // None for dependencies which are new
+    pub old: Option<Version>,
+    pub new: DatabaseEntry,
+}
+
+// Plans the upgrade of the installed packages, in install order. frozen packages are
+// required at their installed version, unless they are in forced
+pub fn plan<S: AsRef<str>>(databases: &[Database], installed: &[InstalledPackage], frozen: &BTreeMap<String, bool>, forced: &[S]) -> Result<Vec<Upgrade>> {
+    for name in forced {
+        if !installed.iter().any(|package| package.name() == name.as_ref()) {
+            return Err(BreadError::NotInstalled { name: name.as_ref().to_string() });
+        }
+    }
+
+    let known = |name: &str| databases.iter().any(|db| db.entries.iter().any(|entry| entry.name == name));
+
+    let mut requested = vec![];
+    for package in installed {
+        let name = package.name();
+        let version = &package.info.package.version;
+
+        // it stays as it is, but whatever it depends on still has to fit it
+        if !known(name) {
+            log::warn!("{} is not in any database, it's left alone", pkg_name(name));
+
+            for (dependency, requirement) in package.info.dependencies.iter().flatten() {
+                if known(dependency) {
+                    requested.push((dependency.to_string(), requirement.to_string()));
+                }
+            }
+
+            continue;
+        }
+
+        let is_frozen = frozen.get(name).cloned().unwrap_or(false) && !forced.iter().any(|forced| forced.as_ref() == name);
+        if !is_frozen {
+            requested.push((name.to_string(), "*".to_string()));
+            continue;
+        }
+
+        if !databases.iter().any(|db| db.entries.iter().any(|entry| entry.name == name && &entry.version == version)) {
+            return Err(BreadError::Config(format!(
+                "{} is frozen at {}, but no database has that version anymore, use --force {} to upgrade it anyway",
+                name, version, name
+            )));
+        }
+
+        log::info!("{} is frozen at {}, use --force {} to upgrade it", pkg_name(name), version, name);
+        requested.push((name.to_string(), format!("={}", version)));
+    }
+
+    let mut upgrades = vec![];
+    for entry in resolve_requirements(databases, &requested)? {
+        let old = installed.iter()
+            .find(|package| package.name() == entry.name)
+            .map(|package| package.info.package.version.clone());
+
+        match &old {
+            Some(old) if *old >= entry.version => continue,
+            _ => upgrades.push(Upgrade { old, new: entry }),
+        }
+    }
+
+    Ok(upgrades)
+}
+
+// Plans the upgrade of everything installed into root against the saved databases
+pub fn plan_installed<S: AsRef<str>, R: AsRef<Path>>(root: R, forced: &[S]) -> Result<Vec<Upgrade>> {
+    let databases = Crumb::load_databases()?;
+    let installed = InstalledPackage::load_all(root)?;
+    let config = Config::load(PATH_CONFIGS)?;
+
+    plan(&databases, &installed, &config.frozen_crumbs, forced)
+}
+
+fn format_size(size: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB"];
+
+    let mut size = size as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 { format!("{} {}", size, units[unit]) } else { format!("{:.1} {}", size, units[unit]) }
+}
+
+// The table of old -> new versions with the download size, shown before upgrading
+pub fn summary(upgrades: &[Upgrade]) -> String {
+    let mut rows = vec![("Package".to_string(), "Old".to_string(), "New".to_string(), "Download".to_string())];
+    for upgrade in upgrades {
+        rows.push((
+            upgrade.new.name.clone(),
+            upgrade.old.as_ref().map(Version::to_string).unwrap_or_else(|| "(new)".to_string()),
+            upgrade.new.version.to_string(),
+            format_size(upgrade.new.size),
+        ));
+    }
+
+    let name_width = rows.iter().map(|row| row.0.len()).max().unwrap_or(0);
+    let old_width = rows.iter().map(|row| row.1.len()).max().unwrap_or(0);
+    let new_width = rows.iter().map(|row| row.2.len()).max().unwrap_or(0);
+
+    let mut table = String::default();
+    for (i, (name, old, new, size)) in rows.iter().enumerate() {
+        let arrow = if i == 0 { "  " } else { "->" };
+        table += &format!("{:<name_width$}  {:>old_width$} {} {:<new_width$}  {:>10}\n", name, old, arrow, new, size,
+            name_width = name_width, old_width = old_width, new_width = new_width);
+    }
+
+    let total: u64 = upgrades.iter().map(|upgrade| upgrade.new.size).sum();
+    table += &format!("\n{} package(s), {} to download", upgrades.len(), format_size(total));
+
+    table
+}
+
+// Downloads every crumb first, then installs them in one transaction
+pub async fn apply<R: AsRef<Path>>(upgrades: &[Upgrade], root: R, insecure: bool) -> Result<()> {
+    let root = root.as_ref();
+    let databases = Crumb::load_databases()?;
+
+    let entries: Vec<DatabaseEntry> = upgrades.iter().map(|upgrade| upgrade.new.clone()).collect();
+    let crumbs = Crumb::download_entries(&databases, &entries, insecure).await?;
+
+    let installed = InstalledPackage::load_all(root)?;
+    let mut transaction = Transaction::new(root)?;
+
+    for (entry, crumb) in entries.iter().zip(&crumbs) {
+        let old = installed.iter().find(|package| package.name() == entry.name);
+
+        if let Err(err) = transaction.install(crumb, old).await {
+            log::error!("Upgrading {} failed, nothing is changed", pkg_name(&entry.name));
+            transaction.rollback()?;
+
+            return Err(err);
+        }
+    }
+
+    transaction.commit()
+}
+
+#[test]
+fn plan_upgrades() {
+    let entries = [
+        ("libc", "1.0", vec![]),
+        ("libc", "1.1", vec![]),
+        ("acl", "2.2", vec![("libc", "^1.0")]),
+        ("coreutils", "8.31", vec![("libc", "^1.0")]),
+        ("coreutils", "8.32", vec![("libc", "^1.1"), ("acl", "^2.2")]),
+        ("bread", "1.0", vec![]),
+        ("bread", "1.1", vec![]),
+    ];
+
+    let databases = vec![Database {
+        name: "test".to_string(),
+        entries: entries.iter()
+            .map(|(name, version, dependencies)| DatabaseEntry {
+                name: name.to_string(),
+                version: version.parse().unwrap(),
+                size: 2048,
+                dependencies: dependencies.iter().map(|(n, r)| (n.to_string(), r.to_string())).collect(),
+                ..DatabaseEntry::new()
+            })
+            .collect(),
+    }];
+
+    let installed_package = |name: &str, version: &str, dependencies: &str| InstalledPackage {
+        path: Path::new("/etc/bread/installed").join(name),
+        info: crate::crumb::CrumbInfo::from_string(format!("[package]\nname = \"{}\"\nversion = \"{}\"\n[scripts]\n[dependencies]\n{}", name, version, dependencies)).unwrap(),
+        manifest: None,
+    };
+
+    let installed: Vec<InstalledPackage> = [("libc", "1.0"), ("coreutils", "8.31"), ("bread", "1.0"), ("hello", "1.0")].iter()
+        .map(|(name, version)| installed_package(name, version, ""))
+        .collect();
+
+    let mut frozen = BTreeMap::new();
+    frozen.insert("bread".to_string(), true);
+
+    let upgrades = plan(&databases, &installed, &frozen, &[] as &[&str]).unwrap();
+    let names: Vec<(Option<String>, String)> = upgrades.iter()
+        .map(|upgrade| (upgrade.old.as_ref().map(Version::to_string), format!("{}@{}", upgrade.new.name, upgrade.new.version)))
+        .collect();
+
+    assert_eq!(vec![
+        (Some("1.0".to_string()), "libc@1.1".to_string()),
+        (None, "acl@2.2".to_string()),
+        (Some("8.31".to_string()), "coreutils@8.32".to_string()),
+    ], names);
+
+    let forced = plan(&databases, &installed, &frozen, &["bread"]).unwrap();
+    assert!(forced.iter().any(|upgrade| upgrade.new.name == "bread"));
+    assert!(matches!(plan(&databases, &installed, &frozen, &["hi"]), Err(BreadError::NotInstalled { .. })));
+
+    // hello isn't in the databases, libc still has to stay at what it requires
+    let mut others: Vec<InstalledPackage> = installed.into_iter().filter(|package| package.name() != "hello").collect();
+    others.push(installed_package("hello", "1.0", "libc = \"~1.0\"\n"));
+    let held = plan(&databases, &others, &frozen, &[] as &[&str]).unwrap();
+    assert!(!held.iter().any(|upgrade| upgrade.new.name == "libc"));
+
+    // a frozen version which is gone from the databases can't be held
+    others.retain(|package| package.name() != "bread");
+    others.push(installed_package("bread", "0.9", ""));
+    assert!(matches!(plan(&databases, &others, &frozen, &[] as &[&str]), Err(BreadError::Config(_))));
+
+    let table = summary(&upgrades);
+    assert!(table.contains("coreutils   8.31 -> 8.32"), "{}", table);
+    assert!(table.ends_with("3 package(s), 6.0 KiB to download"), "{}", table);
+}
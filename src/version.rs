@@ -0,0 +1,218 @@
+// a package version looks like
+// [$EPOCH:]$MAJOR.$MINOR.$PATCH...[~$PRE_RELEASE][-$REVISION]
+//
+//   8.32           plain dotted version, any amount of components
+//   1:2.0          epoch 1, always newer than anything with epoch 0
+//   2.3.1~rc1      pre-release, older than 2.3.1
+//   2.3.1-2        second revision of the 2.3.1 package, newer than 2.3.1
+//
+// components are compared as numbers, so 1.10 is newer than 1.9,
+// missing components count as 0, so 1.0 equals 1.0.0
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer};
+use serde::de::Error as DeError;
+
+#[derive(Debug, Clone)]
+pub struct Version {
+    pub epoch: u64,
+    pub components: Vec<u64>,
+    pub pre_release: Option<String>,
+    pub revision: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseVersionError {
+    pub version: String,
+    pub reason: &'static str,
+}
+
+impl fmt::Display for ParseVersionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid version \"{}\": {}", self.version, self.reason)
+    }
+}
+
+impl std::error::Error for ParseVersionError {}
+
+impl Default for Version {
+    fn default() -> Version {
+        Version {
+            epoch: 0,
+            components: vec![0],
+            pre_release: None,
+            revision: 0,
+        }
+    }
+}
+
+impl FromStr for Version {
+    type Err = ParseVersionError;
+
+    fn from_str(data: &str) -> Result<Version, ParseVersionError> {
+        let error = |reason| ParseVersionError { version: data.to_string(), reason };
+
+        let mut rest = data.trim();
+
+        let mut epoch = 0;
+        if let Some(i) = rest.find(':') {
+            epoch = rest[..i].parse().map_err(|_| error("epoch is not a number"))?;
+            rest = &rest[i + 1..];
+        }
+
+        let mut revision = 0;
+        if let Some(i) = rest.rfind('-') {
+            revision = rest[i + 1..].parse().map_err(|_| error("revision is not a number"))?;
+            rest = &rest[..i];
+        }
+
+        let mut pre_release = None;
+        if let Some(i) = rest.find('~') {
+            let pre = &rest[i + 1..];
+            if pre.is_empty() || !pre.chars().all(|c| c.is_ascii_alphanumeric() || c == '.') {
+                return Err(error("pre-release may only contain letters, digits and dots"));
+            }
+
+            pre_release = Some(pre.to_string());
+            rest = &rest[..i];
+        }
+
+        if rest.is_empty() {
+            return Err(error("version is empty"));
+        }
+
+        let components = rest.split('.')
+            .map(|c| c.parse())
+            .collect::<Result<Vec<u64>, _>>()
+            .map_err(|_| error("components have to be numbers separated by dots"))?;
+
+        Ok(Version { epoch, components, pre_release, revision })
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.epoch != 0 {
+            write!(f, "{}:", self.epoch)?;
+        }
+
+        let components: Vec<String> = self.components.iter().map(|c| c.to_string()).collect();
+        write!(f, "{}", components.join("."))?;
+
+        if let Some(pre_release) = &self.pre_release {
+            write!(f, "~{}", pre_release)?;
+        }
+
+        if self.revision != 0 {
+            write!(f, "-{}", self.revision)?;
+        }
+
+        Ok(())
+    }
+}
+
+// pre-release identifiers are compared like semver does,
+// numbers numerically and everything else alphabetically
+fn compare_pre_release(a: &str, b: &str) -> Ordering {
+    let mut a = a.split('.');
+    let mut b = b.split('.');
+
+    loop {
+        let ordering = match (a.next(), b.next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+
+            (Some(a), Some(b)) => match (a.parse::<u64>(), b.parse::<u64>()) {
+                (Ok(a), Ok(b)) => a.cmp(&b),
+                (Ok(_), Err(_)) => Ordering::Less,
+                (Err(_), Ok(_)) => Ordering::Greater,
+                (Err(_), Err(_)) => a.cmp(b),
+            }
+        };
+
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Version) -> Ordering {
+        let ordering = self.epoch.cmp(&other.epoch);
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+
+        for i in 0..self.components.len().max(other.components.len()) {
+            let ordering = self.components.get(i).unwrap_or(&0).cmp(other.components.get(i).unwrap_or(&0));
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+
+        let ordering = match (&self.pre_release, &other.pre_release) {
+            (None, None) => Ordering::Equal,
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (Some(a), Some(b)) => compare_pre_release(a, b),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+
+        self.revision.cmp(&other.revision)
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Version) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Version) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Version {}
+
+impl<'de> Deserialize<'de> for Version {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Version, D::Error> {
+        let data = String::deserialize(deserializer)?;
+
+        data.parse().map_err(DeError::custom)
+    }
+}
+
+#[test]
+fn version_ordering() {
+    let v = |s: &str| s.parse::<Version>().unwrap();
+
+    assert!(v("8.32") < v("8.320"));
+    assert!(v("1.10") > v("1.9"));
+    assert!(v("2.3.1") > v("2.3"));
+    assert_eq!(v("1.0"), v("1.0.0"));
+    assert!(v("1:0.1") > v("99.0"));
+    assert!(v("2.3.1~rc1") < v("2.3.1"));
+    assert!(v("2.3.1~rc.2") < v("2.3.1~rc.10"));
+    assert!(v("2.3.1-2") > v("2.3.1"));
+    assert!(v("2.3.1-2") < v("2.3.2"));
+}
+
+#[test]
+fn version_display() {
+    for version in &["8.32", "1:2.0", "2.3.1~rc1", "2.3.1-2", "3:1.0.0~beta.1-4"] {
+        assert_eq!(*version, version.parse::<Version>().unwrap().to_string());
+    }
+
+    assert!("".parse::<Version>().is_err());
+    assert!("1.x".parse::<Version>().is_err());
+    assert!("a:1.0".parse::<Version>().is_err());
+    assert!("1.0-r1".parse::<Version>().is_err());
+}